@@ -3,7 +3,6 @@ use std::error;
 use std::fmt;
 use std::io;
 use std::iter::Iterator;
-use std::mem;
 use std::result;
 use std::str::FromStr;
 
@@ -29,6 +28,28 @@ pub enum Tag {
     DecodingError,
     Prefixed,
     CaseInsensitiveFragment,
+    RedirectLoop,
+    PermanentRedirect,
+    WrongCase,
+    OutsideRoot,
+    BodyTooLarge,
+    UncheckableFragment,
+    MetaRefresh,
+    NotPublished,
+    DuplicateAnchor,
+    DnsError,
+    InvalidTel,
+    InsecureLink,
+    NoTextFragment,
+    NoFragmentSoft,
+    TooManyRedirects,
+    Skipped,
+    ExampleHost,
+    ChecksumMismatch,
+    ContentMismatch,
+    UnusedDefinition,
+    MalformedLink,
+    ArchivedAvailable,
 }
 
 impl fmt::Display for Tag {
@@ -50,6 +71,28 @@ impl fmt::Display for Tag {
             Tag::DecodingError => write!(f, "DEC_ERR"),
             Tag::Prefixed => write!(f, "PREFIXED"),
             Tag::CaseInsensitiveFragment => write!(f, "CASE_FRAG"),
+            Tag::RedirectLoop => write!(f, "REDIRECT_LOOP"),
+            Tag::PermanentRedirect => write!(f, "PERM_REDIRECT"),
+            Tag::WrongCase => write!(f, "WRONG_CASE"),
+            Tag::OutsideRoot => write!(f, "OUTSIDE_ROOT"),
+            Tag::BodyTooLarge => write!(f, "BODY_TOO_LARGE"),
+            Tag::UncheckableFragment => write!(f, "UNCHECKABLE_FRAG"),
+            Tag::MetaRefresh => write!(f, "META_REFRESH"),
+            Tag::NotPublished => write!(f, "NOT_PUBLISHED"),
+            Tag::DuplicateAnchor => write!(f, "DUP_ANCHOR"),
+            Tag::DnsError => write!(f, "DNS_ERR"),
+            Tag::InvalidTel => write!(f, "TEL_ERR"),
+            Tag::InsecureLink => write!(f, "INSECURE"),
+            Tag::NoTextFragment => write!(f, "NO_TEXT_FRAG"),
+            Tag::NoFragmentSoft => write!(f, "NO_FRAG_SOFT"),
+            Tag::TooManyRedirects => write!(f, "TOO_MANY_REDIRECTS"),
+            Tag::Skipped => write!(f, "SKIPPED"),
+            Tag::ExampleHost => write!(f, "EXAMPLE_HOST"),
+            Tag::ChecksumMismatch => write!(f, "CHECKSUM_MISMATCH"),
+            Tag::ContentMismatch => write!(f, "CONTENT_MISMATCH"),
+            Tag::UnusedDefinition => write!(f, "UNUSED_DEF"),
+            Tag::MalformedLink => write!(f, "MALFORMED"),
+            Tag::ArchivedAvailable => write!(f, "ARCHIVED"),
         }
     }
 }
@@ -70,8 +113,31 @@ impl FromStr for Tag {
             "DIR" => Ok(Tag::Directory),
             "NO_MIME" => Ok(Tag::NoMime),
             "MIME" => Ok(Tag::UnrecognizedMime),
+            "DEC_ERR" => Ok(Tag::DecodingError),
             "PREFIXED" => Ok(Tag::Prefixed),
             "CASE_FRAG" => Ok(Tag::CaseInsensitiveFragment),
+            "REDIRECT_LOOP" => Ok(Tag::RedirectLoop),
+            "PERM_REDIRECT" => Ok(Tag::PermanentRedirect),
+            "WRONG_CASE" => Ok(Tag::WrongCase),
+            "OUTSIDE_ROOT" => Ok(Tag::OutsideRoot),
+            "BODY_TOO_LARGE" => Ok(Tag::BodyTooLarge),
+            "UNCHECKABLE_FRAG" => Ok(Tag::UncheckableFragment),
+            "META_REFRESH" => Ok(Tag::MetaRefresh),
+            "NOT_PUBLISHED" => Ok(Tag::NotPublished),
+            "DUP_ANCHOR" => Ok(Tag::DuplicateAnchor),
+            "DNS_ERR" => Ok(Tag::DnsError),
+            "TEL_ERR" => Ok(Tag::InvalidTel),
+            "INSECURE" => Ok(Tag::InsecureLink),
+            "NO_TEXT_FRAG" => Ok(Tag::NoTextFragment),
+            "NO_FRAG_SOFT" => Ok(Tag::NoFragmentSoft),
+            "TOO_MANY_REDIRECTS" => Ok(Tag::TooManyRedirects),
+            "SKIPPED" => Ok(Tag::Skipped),
+            "EXAMPLE_HOST" => Ok(Tag::ExampleHost),
+            "CHECKSUM_MISMATCH" => Ok(Tag::ChecksumMismatch),
+            "CONTENT_MISMATCH" => Ok(Tag::ContentMismatch),
+            "UNUSED_DEF" => Ok(Tag::UnusedDefinition),
+            "MALFORMED" => Ok(Tag::MalformedLink),
+            "ARCHIVED" => Ok(Tag::ArchivedAvailable),
             s if s.starts_with("HTTP_") => u16::from_str(&s[5..])
                 .ok()
                 .and_then(|s| StatusCode::from_u16(s).ok())
@@ -85,11 +151,52 @@ impl FromStr for Tag {
 #[derive(Debug)]
 pub struct Error {
     pub tag: Tag,
-    msgs: Vec<Cow<'static, str>>,
+    msgs: Vec<(Cow<'static, str>, Cow<'static, str>)>,
     cause: Option<Box<dyn error::Error + Sync + Send + 'static>>,
 }
 
 impl Tag {
+    /// Every tag except `HttpStatus`, which has one variant per status code.
+    pub const ALL: &'static [Tag] = &[
+        Tag::Ok,
+        Tag::HttpError,
+        Tag::Timeout,
+        Tag::IoError,
+        Tag::NoDocument,
+        Tag::NoFragment,
+        Tag::Protocol,
+        Tag::Absolute,
+        Tag::Directory,
+        Tag::InvalidUrl,
+        Tag::NoMime,
+        Tag::UnrecognizedMime,
+        Tag::DecodingError,
+        Tag::Prefixed,
+        Tag::CaseInsensitiveFragment,
+        Tag::RedirectLoop,
+        Tag::PermanentRedirect,
+        Tag::WrongCase,
+        Tag::OutsideRoot,
+        Tag::BodyTooLarge,
+        Tag::UncheckableFragment,
+        Tag::MetaRefresh,
+        Tag::NotPublished,
+        Tag::DuplicateAnchor,
+        Tag::DnsError,
+        Tag::InvalidTel,
+        Tag::InsecureLink,
+        Tag::NoTextFragment,
+        Tag::NoFragmentSoft,
+        Tag::TooManyRedirects,
+        Tag::Skipped,
+        Tag::ExampleHost,
+        Tag::ChecksumMismatch,
+        Tag::ContentMismatch,
+        Tag::UnusedDefinition,
+        Tag::MalformedLink,
+        Tag::ArchivedAvailable,
+    ];
+
     pub fn as_error(&self) -> Error {
         Error {
             tag: *self,
@@ -97,14 +204,86 @@ impl Tag {
             cause: None,
         }
     }
+
+    pub fn is_warning(&self) -> bool {
+        matches!(
+            *self,
+            Tag::Prefixed
+                | Tag::CaseInsensitiveFragment
+                | Tag::PermanentRedirect
+                | Tag::UncheckableFragment
+                | Tag::DuplicateAnchor
+                | Tag::InsecureLink
+                | Tag::NoFragmentSoft
+                | Tag::Skipped
+                | Tag::ExampleHost
+                | Tag::UnusedDefinition
+        )
+    }
+
+    /// A short human readable description of the tag.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            Tag::Ok => "ok",
+            Tag::HttpError => "http error",
+            Tag::Timeout => "timeout",
+            Tag::IoError => "io error",
+            Tag::InvalidUrl => "invalid url",
+            Tag::HttpStatus(_) => "unexpected http status",
+            Tag::NoDocument => "document not found",
+            Tag::NoFragment => "fragment not found",
+            Tag::Protocol => "unrecognized protocol",
+            Tag::Absolute => "unhandled absolute path",
+            Tag::Directory => "document is a directory",
+            Tag::NoMime => "no mime type",
+            Tag::UnrecognizedMime => "unrecognized mime type",
+            Tag::DecodingError => "decoding error",
+            Tag::Prefixed => "prefixed fragment",
+            Tag::CaseInsensitiveFragment => "case-insensitive fragment",
+            Tag::RedirectLoop => "redirect loop",
+            Tag::PermanentRedirect => "permanent redirect",
+            Tag::WrongCase => "document found case-insensitively",
+            Tag::OutsideRoot => "path outside of document root",
+            Tag::BodyTooLarge => "response body too large",
+            Tag::UncheckableFragment => "uncheckable fragment",
+            Tag::MetaRefresh => "meta refresh redirect",
+            Tag::NotPublished => "document not in manifest",
+            Tag::DuplicateAnchor => "duplicate anchor",
+            Tag::DnsError => "dns error",
+            Tag::InvalidTel => "invalid telephone number",
+            Tag::InsecureLink => "insecure link",
+            Tag::NoTextFragment => "text fragment not found",
+            Tag::NoFragmentSoft => "fragment not found in static html",
+            Tag::TooManyRedirects => "too many redirects",
+            Tag::Skipped => "link skipped",
+            Tag::ExampleHost => "placeholder host",
+            Tag::ChecksumMismatch => "checksum mismatch",
+            Tag::ContentMismatch => "unexpected content",
+            Tag::UnusedDefinition => "unused link definition",
+            Tag::MalformedLink => "malformed link",
+            Tag::ArchivedAvailable => "archived copy available",
+        }
+    }
 }
 
 impl Error {
-    pub fn context(mut self, msg: Cow<'static, str>) -> Self {
-        self.msgs.push(msg);
+    pub fn context<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        self.msgs.push((key.into(), value.into()));
         self
     }
 
+    /// Iterates over the context key/value pairs, most recently added first.
+    pub fn contexts(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.msgs
+            .iter()
+            .rev()
+            .map(|(key, value)| (key.as_ref(), value.as_ref()))
+    }
+
     #[allow(dead_code)]
     pub fn cause(&self) -> Option<&(dyn error::Error + Sync + Send)> {
         self.cause.as_ref().map(|e| e.as_ref())
@@ -118,7 +297,7 @@ impl Error {
         }
     }
 
-    pub fn iter(&self) -> ErrorIter {
+    pub fn iter(&self) -> ErrorIter<'_> {
         ErrorIter {
             count: 0,
             err: self,
@@ -141,10 +320,10 @@ impl<'a> Iterator for ErrorIter<'a> {
             self.count += 1;
             Some(format!("{}", self.err))
         } else if self.count <= self.err.msgs.len() {
-            let elem = &self.err.msgs[self.err.msgs.len() - self.count];
+            let (key, value) = &self.err.msgs[self.err.msgs.len() - self.count];
             self.count += 1;
-            Some(format!("  context: {elem}"))
-        } else if let Some(cause) = mem::replace(&mut self.cause, None) {
+            Some(format!("  context: {key} = {value}"))
+        } else if let Some(cause) = self.cause.take() {
             let s = format!("  caused by: {cause}");
             self.cause = cause.source();
             Some(s)
@@ -181,30 +360,35 @@ impl fmt::Display for Error {
             Tag::DecodingError => write!(f, "Decoding error"),
             Tag::Prefixed => write!(f, "Fragment not found without prefix"),
             Tag::CaseInsensitiveFragment => write!(f, "Fragment not found case-sensitively"),
+            Tag::RedirectLoop => write!(f, "Redirect loop"),
+            Tag::PermanentRedirect => write!(f, "Resolved via permanent redirect"),
+            Tag::WrongCase => write!(f, "Document found case-insensitively"),
+            Tag::OutsideRoot => write!(f, "Path outside of document root"),
+            Tag::BodyTooLarge => write!(f, "Response body too large"),
+            Tag::UncheckableFragment => write!(f, "Fragment not checkable in this content type"),
+            Tag::MetaRefresh => write!(f, "HTML meta refresh redirect"),
+            Tag::NotPublished => write!(f, "Document not in manifest"),
+            Tag::DuplicateAnchor => write!(f, "Fragment matches several headings"),
+            Tag::DnsError => write!(f, "Host name not resolved"),
+            Tag::InvalidTel => write!(f, "Invalid telephone number"),
+            Tag::InsecureLink => write!(f, "Link reachable over HTTPS"),
+            Tag::NoTextFragment => write!(f, "Text fragment not found"),
+            Tag::NoFragmentSoft => write!(f, "Fragment not found in static HTML"),
+            Tag::TooManyRedirects => write!(f, "Too many redirects"),
+            Tag::Skipped => write!(f, "Link skipped"),
+            Tag::ExampleHost => write!(f, "Placeholder host"),
+            Tag::ChecksumMismatch => write!(f, "Checksum mismatch"),
+            Tag::ContentMismatch => write!(f, "Unexpected content"),
+            Tag::UnusedDefinition => write!(f, "Unused link definition"),
+            Tag::MalformedLink => write!(f, "Malformed link"),
+            Tag::ArchivedAvailable => write!(f, "Archived copy available"),
         }
     }
 }
 
 impl error::Error for Error {
     fn description(&self) -> &str {
-        match self.tag {
-            Tag::Ok => "ok",
-            Tag::HttpError => "http error",
-            Tag::Timeout => "timeout",
-            Tag::IoError => "io error",
-            Tag::InvalidUrl => "invalid url",
-            Tag::HttpStatus(_) => "unexpected http status",
-            Tag::NoDocument => "document not found",
-            Tag::NoFragment => "fragment not found",
-            Tag::Protocol => "unrecognized protocol",
-            Tag::Absolute => "unhandled absolute path",
-            Tag::Directory => "document is a directory",
-            Tag::NoMime => "no mime type",
-            Tag::UnrecognizedMime => "unrecognized mime type",
-            Tag::DecodingError => "decoding error",
-            Tag::Prefixed => "prefixed fragmendt",
-            Tag::CaseInsensitiveFragment => "case-insensitive fragmendt",
-        }
+        self.tag.description()
     }
 
     fn cause(&self) -> Option<&dyn error::Error> {
@@ -220,6 +404,12 @@ impl From<io::Error> for Error {
                 msgs: vec![],
                 cause: Some(Box::new(err)),
             }
+        } else if err.kind() == io::ErrorKind::TimedOut {
+            Error {
+                tag: Tag::Timeout,
+                msgs: vec![(Cow::from("phase"), Cow::from("read"))],
+                cause: Some(Box::new(err)),
+            }
         } else {
             Error {
                 tag: Tag::IoError,
@@ -233,9 +423,10 @@ impl From<io::Error> for Error {
 impl From<reqwest::Error> for Error {
     fn from(err: reqwest::Error) -> Self {
         if err.is_timeout() {
+            let phase = if err.is_connect() { "connect" } else { "read" };
             Error {
                 tag: Tag::Timeout,
-                msgs: vec![],
+                msgs: vec![(Cow::from("phase"), Cow::from(phase))],
                 cause: Some(Box::new(err)),
             }
         } else {
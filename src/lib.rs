@@ -5,74 +5,244 @@ use std::borrow::Cow;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::Cursor;
 use std::io::Read;
+use std::iter;
+use std::ops::Range;
 use std::path::Path;
 use std::path::PathBuf;
 use std::result;
 use std::str::FromStr;
 use std::sync;
+use std::time::Duration;
+use std::time::Instant;
 
 use bytecount::count;
 use encoding_rs::Encoding;
+use htmlstream::HTMLTagState;
 use lazy_static::lazy_static;
 use log::debug;
 use pulldown_cmark::CowStr;
 use pulldown_cmark::Event;
+use pulldown_cmark::LinkType;
 use pulldown_cmark::OffsetIter;
+use pulldown_cmark::Options;
 use pulldown_cmark::Parser;
 use regex::Regex;
+use reqwest::header::HeaderMap;
 use reqwest::header::HeaderValue;
+use reqwest::header::IntoHeaderName;
 use reqwest::header::ACCEPT;
+use reqwest::header::CONTENT_ENCODING;
 use reqwest::header::CONTENT_TYPE;
+use reqwest::header::ETAG;
+use reqwest::header::IF_MODIFIED_SINCE;
+use reqwest::header::IF_NONE_MATCH;
+use reqwest::header::LAST_MODIFIED;
+use sha2::Digest;
+use sha2::Sha256;
+use url::Position;
 use url::Url;
 
-use crate::error::Error;
-use crate::error::Result;
-use crate::error::Tag;
 use crate::link::Link;
 
+pub use crate::error::Error;
+pub use crate::error::Result;
+pub use crate::error::Tag;
+
+/// The prefix GitHub adds to the ids of user generated content.
+const GITHUB_PREFIX: &str = "user-content-";
+
+/// The number of redirects followed in a row by default, as in reqwest.
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+pub const DEFAULT_USER_AGENT: &str = concat!("linky/", env!("CARGO_PKG_VERSION"));
+
+/// The Accept header sent by default, preferring the formats that can be
+/// checked for anchors.
+pub const DEFAULT_ACCEPT: &str = "text/html, text/markdown;q=0.9, */*;q=0.1";
+
+/// The Wayback Machine availability API, for looking up archived copies of dead links.
+pub const WAYBACK_API: &str = "https://archive.org/wayback/available";
+
 lazy_static! {
     static ref MARKDOWN_CONTENT_TYPE: mime::Mime = "text/markdown; charset=UTF-8".parse().unwrap();
+    static ref ASCIIDOC_CONTENT_TYPE: mime::Mime = "text/asciidoc; charset=UTF-8".parse().unwrap();
 }
 
 enum Format {
     Html,
     Markdown,
+    Asciidoc,
+}
+
+/// The scheme used for generating anchors from Markdown headings.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AnchorStyle {
+    #[default]
+    Github,
+    Gitlab,
+    Pandoc,
 }
 
+impl AnchorStyle {
+    /// Returns the built-in anchor transform of the style.
+    pub fn id_transform(&self) -> &'static dyn ToId {
+        match *self {
+            AnchorStyle::Github => &GithubId,
+            AnchorStyle::Gitlab => &GitlabId,
+            AnchorStyle::Pandoc => &PandocId,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Document<'a> {
     pub ids: HashSet<Cow<'a, str>>,
+    pub final_url: Option<Url>,
+    pub redirects: Vec<(reqwest::StatusCode, Url)>,
+    /// Set to the body size limit if the document was parsed from a truncated body.
+    pub truncated_at: Option<u64>,
+    /// Set to the content type if it can't be inspected for anchors.
+    pub uncheckable: Option<mime::Mime>,
+    /// The href of the first `<base>` element of an HTML document.
+    pub base_href: Option<String>,
+    /// The target URL of a `<meta http-equiv="refresh">` element.
+    pub refresh: Option<String>,
+    /// The number of lines of a local document.
+    pub lines: Option<usize>,
+    /// Anchors generated by more than one heading of the document.
+    pub duplicate_anchors: HashSet<String>,
+    /// The `ETag` header of a remote document, for revalidating it later.
+    pub etag: Option<String>,
+    /// The `Last-Modified` header of a remote document, for revalidating it later.
+    pub last_modified: Option<String>,
+    /// The `https:` equivalent of an `http:` document, if it is reachable too.
+    pub https_url: Option<Url>,
+    /// The text content of the document with whitespace collapsed, kept only
+    /// for checking expected content.
+    pub text: Option<String>,
+    /// The lowercased text content of the document, kept only for checking
+    /// text fragments.
+    pub lowercase_text: Option<String>,
+    /// The number of decoded response body bytes read for a remote document.
+    pub bytes_read: Option<u64>,
 }
 
 impl<'a> Document<'a> {
     pub fn empty() -> Self {
         Document {
             ids: HashSet::new(),
+            final_url: None,
+            redirects: vec![],
+            truncated_at: None,
+            uncheckable: None,
+            base_href: None,
+            refresh: None,
+            lines: None,
+            duplicate_anchors: HashSet::new(),
+            etag: None,
+            last_modified: None,
+            https_url: None,
+            text: None,
+            lowercase_text: None,
+            bytes_read: None,
         }
     }
 
     #[cfg(test)]
-    pub fn new() -> Self {
+    fn new() -> Self {
         Self::from(&[])
     }
 
+    /// Resolves the base URL for relative links in a document fetched from `url`.
+    pub fn base_url(&self, url: &Url) -> Url {
+        self.base_href
+            .as_ref()
+            .and_then(|href| url.join(href).ok())
+            .unwrap_or_else(|| url.clone())
+    }
+
     #[cfg(test)]
     pub fn from(ids: &'a [&'a str]) -> Self {
         Document {
             ids: [""].iter().chain(ids).cloned().map(Cow::from).collect(),
+            final_url: None,
+            redirects: vec![],
+            truncated_at: None,
+            uncheckable: None,
+            base_href: None,
+            refresh: None,
+            lines: None,
+            duplicate_anchors: HashSet::new(),
+            etag: None,
+            last_modified: None,
+            https_url: None,
+            text: None,
+            lowercase_text: None,
+            bytes_read: None,
         }
     }
 
-    fn parse<R: Read>(mut reader: R, content_type: &mime::Mime) -> Result<Document<'a>> {
+    /// Returns the anchors of a document with the given content type without checking any links.
+    ///
+    /// Markdown headings are turned into anchors by `id_transform`, e.g.
+    /// `AnchorStyle::Github.id_transform()` or a custom `ToId` implementation.
+    pub fn anchors_from_str(
+        buffer: &str,
+        content_type: &mime::Mime,
+        id_transform: &dyn ToId,
+    ) -> Result<HashSet<String>> {
+        let document = Document::parse_with(
+            buffer.as_bytes(),
+            content_type,
+            id_transform,
+            encoding_rs::UTF_8,
+            KeepText::default(),
+        )?;
+        Ok(document
+            .ids
+            .into_iter()
+            .filter(|id| !id.is_empty())
+            .map(Cow::into_owned)
+            .collect())
+    }
+
+    #[cfg(test)]
+    fn parse<R: Read>(reader: R, content_type: &mime::Mime) -> Result<Document<'a>> {
+        let keep_text = KeepText {
+            original: true,
+            lowercase: true,
+        };
+        Self::parse_with(
+            reader,
+            content_type,
+            &GithubId,
+            encoding_rs::UTF_8,
+            keep_text,
+        )
+    }
+
+    fn parse_with<R: Read>(
+        mut reader: R,
+        content_type: &mime::Mime,
+        id_transform: &dyn ToId,
+        charset_fallback: &'static Encoding,
+        keep_text: KeepText,
+    ) -> Result<Document<'a>> {
         let format = match (content_type.type_(), content_type.subtype().as_str()) {
             (mime::TEXT, "html") => Format::Html,
             (mime::TEXT, "markdown") => Format::Markdown,
+            (mime::TEXT, "asciidoc") => Format::Asciidoc,
             _ => {
-                return Ok(Document::empty());
+                return Ok(Document {
+                    uncheckable: Some(content_type.clone()),
+                    ..Document::empty()
+                });
             }
         };
 
@@ -81,50 +251,141 @@ impl<'a> Document<'a> {
             .map(|v| v.as_ref().to_string());
         debug!("http charset hint: {:?}", &charset_hint);
 
-        let chars = read_chars(&mut reader, charset_hint)?;
+        let chars = read_chars(&mut reader, charset_hint, charset_fallback)?;
 
+        let mut base_href = None;
+        let mut refresh = None;
+        let mut duplicate_anchors = HashSet::new();
+        let mut text = String::new();
         let ids = match format {
             Format::Markdown => {
                 let mut headers = Headers::new();
-                MdAnchorParser::from_buffer(&chars, &GithubId, &mut headers)
+                let ids = MdAnchorParser::from_buffer(&chars, id_transform, &mut headers)
                     .map(Cow::from)
-                    .collect()
+                    .collect();
+                // Different heading texts may still collide on the same id
+                let mut counts = HashMap::new();
+                for (text, count) in &headers.0 {
+                    *counts.entry(id_transform.to_id(text, 0)).or_insert(0) += count;
+                }
+                duplicate_anchors = counts
+                    .into_iter()
+                    .filter(|&(_, count)| count > 1)
+                    .map(|(id, _)| id)
+                    .collect();
+                if keep_text.any() {
+                    text = markdown_text(&chars);
+                }
+                ids
+            }
+            Format::Asciidoc => {
+                if keep_text.any() {
+                    text = chars.clone();
+                }
+                asciidoc_ids(&chars).into_iter().map(Cow::from).collect()
             }
             Format::Html => {
                 let mut result = HashSet::new();
+                let mut in_script = false;
                 for (_, tag) in htmlstream::tag_iter(&chars) {
+                    match tag.state {
+                        HTMLTagState::Text if !in_script && keep_text.any() => {
+                            text.push_str(&decode_html_entities(&tag.html))
+                        }
+                        HTMLTagState::Text => {}
+                        _ if tag.name == "script" || tag.name == "style" => {
+                            in_script = tag.state == HTMLTagState::Opening;
+                        }
+                        _ if keep_text.any() && !INLINE_ELEMENTS.contains(&tag.name.as_str()) => {
+                            text.push(' ')
+                        }
+                        _ => {}
+                    }
                     for (_, attr) in htmlstream::attr_iter(&tag.attributes) {
                         if attr.name == "id" || (tag.name == "a" && attr.name == "name") {
                             result.insert(Cow::from(attr.value));
+                        } else if tag.name == "base" && attr.name == "href" && base_href.is_none() {
+                            base_href = Some(decode_html_entities(&attr.value));
                         }
                     }
+                    if tag.name == "meta" && refresh.is_none() {
+                        refresh = meta_refresh_target(&tag.attributes);
+                    }
                 }
                 result
             }
         };
 
-        Ok(Document { ids })
+        let text = Some(normalize_text(&text)).filter(|_| keep_text.any());
+        Ok(Document {
+            ids,
+            final_url: None,
+            redirects: vec![],
+            truncated_at: None,
+            uncheckable: None,
+            base_href,
+            refresh,
+            lines: Some(chars.lines().count()),
+            duplicate_anchors,
+            etag: None,
+            last_modified: None,
+            https_url: None,
+            lowercase_text: text
+                .as_deref()
+                .filter(|_| keep_text.lowercase)
+                .map(str::to_lowercase),
+            text: text.filter(|_| keep_text.original),
+            bytes_read: None,
+        })
     }
 }
 
 pub struct FragResolver<'a> {
     prefixes: HashSet<Cow<'a, str>>,
+    github_compat: bool,
+    fragment_soft_fail: bool,
 }
 
 impl<'a> FragResolver<'a> {
     #[cfg(test)]
-    pub fn new() -> Self {
+    fn new() -> Self {
         FragResolver {
             prefixes: HashSet::new(),
+            github_compat: false,
+            fragment_soft_fail: false,
         }
     }
 
     pub fn from(prefixes: &'a [&'a str]) -> Self {
         FragResolver {
             prefixes: prefixes.iter().cloned().map(Cow::from).collect(),
+            github_compat: false,
+            fragment_soft_fail: false,
         }
     }
 
+    /// Accepts fragments of links to github.com matching anchors with the
+    /// `user-content-` prefix, the way GitHub resolves them on rendered pages.
+    pub fn github_compat(mut self, github_compat: bool) -> Self {
+        self.github_compat = github_compat;
+        self
+    }
+
+    /// Reports fragments missing from remote documents as NO_FRAG_SOFT, since
+    /// their anchors may be added by scripts.
+    pub fn fragment_soft_fail(mut self, fragment_soft_fail: bool) -> Self {
+        self.fragment_soft_fail = fragment_soft_fail;
+        self
+    }
+
+    fn is_github_anchor(&self, base: &Link, fragment: &str, document: &Document) -> bool {
+        self.github_compat
+            && matches!(*base, Link::Url(ref url) if url.host_str() == Some("github.com"))
+            && document
+                .ids
+                .contains(format!("{GITHUB_PREFIX}{fragment}").as_str())
+    }
+
     fn find_prefix(&self, fragment: &str, document: &Document<'_>) -> Option<&str> {
         if document.ids.contains(&Cow::from(fragment)) {
             return Some("");
@@ -148,8 +409,8 @@ impl<'a> FragResolver<'a> {
                     self.find_prefix(&fragment_lc, document).map(|_| {
                         Err(Tag::CaseInsensitiveFragment
                             .as_error()
-                            .context(Cow::from(format!("anchor = #{fragment_lc}")))
-                            .context(Cow::from(format!("fragment = #{fragment}"))))
+                            .context("anchor", format!("#{fragment_lc}"))
+                            .context("fragment", format!("#{fragment}")))
                     })
                 } else {
                     None
@@ -158,7 +419,7 @@ impl<'a> FragResolver<'a> {
                     Some(res) => res,
                     None => Err(Tag::NoFragment
                         .as_error()
-                        .context(Cow::from(format!("fragment = #{fragment}")))),
+                        .context("fragment", format!("#{fragment}"))),
                 }
             }
         }
@@ -168,8 +429,8 @@ impl<'a> FragResolver<'a> {
             } else {
                 Err(Tag::Prefixed
                     .as_error()
-                    .context(Cow::from(format!("prefix = {prefix}")))
-                    .context(Cow::from(format!("fragment = #{fragment}"))))
+                    .context("prefix", prefix.to_string())
+                    .context("fragment", format!("#{fragment}")))
             }
         })
     }
@@ -180,52 +441,737 @@ impl<'a> FragResolver<'a> {
         base: &Link,
         fragment: &Option<String>,
     ) -> Option<result::Result<(), sync::Arc<Error>>> {
-        document.as_ref().map(|document| {
-            document
-                .as_ref()
-                .map_err(std::clone::Clone::clone)
-                .and_then(|document| {
-                    if let Some(ref fragment) = *fragment {
-                        self.fragment(document, fragment).map_err(|err| {
-                            sync::Arc::new(err.context(Cow::from(format!("link = {base}"))))
-                        })
-                    } else {
-                        Ok(())
+        document
+            .as_ref()
+            .map(|document| self.document(document, base, fragment))
+    }
+
+    pub fn document(
+        &self,
+        document: &result::Result<Document, sync::Arc<Error>>,
+        base: &Link,
+        fragment: &Option<String>,
+    ) -> result::Result<(), sync::Arc<Error>> {
+        document
+            .as_ref()
+            .map_err(std::clone::Clone::clone)
+            .and_then(|document| {
+                if let Some(ref fragment) = *fragment {
+                    if let Some(ref content_type) = document.uncheckable {
+                        if !fragment.is_empty() {
+                            return Err(sync::Arc::new(
+                                Tag::UncheckableFragment
+                                    .as_error()
+                                    .context("content-type", content_type.to_string())
+                                    .context("fragment", format!("#{fragment}"))
+                                    .context("link", base.to_string()),
+                            ));
+                        }
                     }
-                })
-        })
+                    let fragment = match fragment.find(":~:") {
+                        Some(pos) => {
+                            let directives = fragment[pos + 3..]
+                                .split('&')
+                                .filter_map(|directive| directive.strip_prefix("text="));
+                            let text = document.lowercase_text.as_deref().unwrap_or_default();
+                            for directive in directives {
+                                if !matches_text_directive(text, directive) {
+                                    return Err(sync::Arc::new(
+                                        Tag::NoTextFragment
+                                            .as_error()
+                                            .context("text", directive.to_string())
+                                            .context("link", base.to_string()),
+                                    ));
+                                }
+                            }
+                            if pos == 0 {
+                                return Ok(());
+                            }
+                            &fragment[..pos]
+                        }
+                        None => fragment.as_str(),
+                    };
+                    if let (Some(lines), Some(range)) = (document.lines, line_anchor(fragment)) {
+                        return if *range.start() >= 1
+                            && range.start() <= range.end()
+                            && *range.end() <= lines
+                        {
+                            Ok(())
+                        } else {
+                            Err(sync::Arc::new(
+                                Tag::NoFragment
+                                    .as_error()
+                                    .context("lines", lines.to_string())
+                                    .context("fragment", format!("#{fragment}"))
+                                    .context("link", base.to_string()),
+                            ))
+                        };
+                    }
+                    if self.is_github_anchor(base, fragment, document) {
+                        return Ok(());
+                    }
+                    self.fragment(document, fragment)
+                        .map_err(|err| match document.truncated_at {
+                            Some(limit) if err.tag == Tag::NoFragment => Tag::BodyTooLarge
+                                .as_error()
+                                .context("limit", limit.to_string())
+                                .context("fragment", format!("#{fragment}")),
+                            _ => err,
+                        })
+                        .map_err(|mut err| {
+                            if self.fragment_soft_fail
+                                && err.tag == Tag::NoFragment
+                                && matches!(*base, Link::Url(_))
+                            {
+                                err.tag = Tag::NoFragmentSoft;
+                            }
+                            sync::Arc::new(err.context("link", base.to_string()))
+                        })
+                } else {
+                    Ok(())
+                }
+            })
+    }
+}
+
+/// The forms of the text content of a document to keep when parsing it.
+#[derive(Clone, Copy, Default)]
+struct KeepText {
+    original: bool,
+    lowercase: bool,
+}
+
+impl KeepText {
+    fn any(self) -> bool {
+        self.original || self.lowercase
     }
 }
 
+/// Checks whether a text directive `[prefix-,]start[,end][,-suffix]` of a
+/// text fragment matches the normalized text content of a document.
+fn matches_text_directive(text: &str, directive: &str) -> bool {
+    let decode = |part: &str| {
+        normalize_text(&urlencoding::decode(part).unwrap_or_else(|_| part.to_string()))
+            .to_lowercase()
+    };
+    let mut parts: VecDeque<&str> = directive.split(',').collect();
+    let prefix = match parts.front() {
+        Some(part) if part.ends_with('-') => parts
+            .pop_front()
+            .map(|part| decode(&part[..part.len() - 1])),
+        _ => None,
+    };
+    let suffix = match parts.back() {
+        Some(part) if part.starts_with('-') && parts.len() > 1 => {
+            parts.pop_back().map(|part| decode(&part[1..]))
+        }
+        _ => None,
+    };
+    let (start, end) = match (parts.pop_front(), parts.pop_front(), parts.is_empty()) {
+        (Some(start), end, true) if !start.is_empty() => (decode(start), end.map(decode)),
+        _ => return false,
+    };
+    let has_suffix = |pos: usize| {
+        suffix
+            .as_ref()
+            .is_none_or(|suffix| text[pos..].trim_start().starts_with(suffix.as_str()))
+    };
+    text.match_indices(start.as_str()).any(|(pos, _)| {
+        let after_start = pos + start.len();
+        prefix
+            .as_ref()
+            .is_none_or(|prefix| text[..pos].trim_end().ends_with(prefix.as_str()))
+            && match end {
+                Some(ref end) => text[after_start..]
+                    .match_indices(end.as_str())
+                    .any(|(end_pos, _)| has_suffix(after_start + end_pos + end.len())),
+                None => has_suffix(after_start),
+            }
+    })
+}
+
+lazy_static! {
+    static ref LINE_ANCHOR: Regex = Regex::new(r"^L(\d+)(?:-L(\d+))?$").unwrap();
+}
+
+/// Parses GitHub style line anchors like `L10` and `L10-L20`.
+fn line_anchor(fragment: &str) -> Option<std::ops::RangeInclusive<usize>> {
+    let captures = LINE_ANCHOR.captures(fragment)?;
+    let start = captures[1].parse().ok()?;
+    let end = match captures.get(2) {
+        Some(end) => end.as_str().parse().ok()?,
+        None => start,
+    };
+    Some(start..=end)
+}
+
+/// Checks a single link, fetching the target document unless one is supplied.
+///
+/// Callers checking several fragments of the same base link can fetch the
+/// document once using `Client::fetch_link` and pass it in for each fragment.
+///
+/// Unlike `FragResolver::link`, this returns a plain result rather than an
+/// `Option`: `None` stands for a link that was never fetched, but this
+/// function always has a document to check against, either the one supplied
+/// or one it just fetched. Callers that skip links, e.g. without a client,
+/// represent that themselves.
+pub fn check_link(
+    client: &Client,
+    resolver: &FragResolver,
+    link: &Link,
+    fragment: &Option<String>,
+    urldecode: bool,
+    document: Option<&result::Result<Document, sync::Arc<Error>>>,
+) -> result::Result<(), sync::Arc<Error>> {
+    let fragment = if client.checks_fragments(link) {
+        fragment
+    } else {
+        &None
+    };
+    let fetched;
+    let document = match document {
+        Some(document) => document,
+        None => {
+            fetched = client.fetch_link(urldecode, link);
+            &fetched
+        }
+    };
+    resolver.document(document, link, fragment).and_then(|()| {
+        document
+            .as_ref()
+            .map_err(std::clone::Clone::clone)
+            .and_then(|document| {
+                client
+                    .check_duplicate_anchor(document, fragment)
+                    .and_then(|()| client.check_redirects(document))
+                    .and_then(|()| client.check_https(document))
+                    .map_err(|err| sync::Arc::new(err.context("link", link.to_string())))
+            })
+    })
+}
+
+/// Timeouts for HTTP requests; Unset timeouts use the reqwest defaults.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timeouts {
+    /// Time limit for establishing a connection.
+    pub connect: Option<Duration>,
+    /// Time limit for a whole request, including reading the response body.
+    pub read: Option<Duration>,
+    /// Time limit for fetching a remote document, including redirects.
+    pub deadline: Option<Duration>,
+}
+
 pub struct Client {
     inner: reqwest::blocking::Client,
+    host_headers: HashMap<String, HeaderMap>,
+    follow: bool,
     redirects: sync::Arc<sync::Mutex<Vec<(reqwest::StatusCode, reqwest::Url)>>>,
+    /// A redirect to another host that reqwest stopped at, to be followed without host headers.
+    stopped_at: sync::Arc<sync::Mutex<Option<reqwest::Url>>>,
+    /// The URL first requested by `send`, so that redirects back to it are loops.
+    origin: sync::Arc<sync::Mutex<Option<reqwest::Url>>>,
+    accepted_statuses: HashSet<reqwest::StatusCode>,
+    warn_permanent_redirects: bool,
+    case_sensitive_paths: bool,
+    max_body_bytes: Option<u64>,
+    warn_duplicate_anchors: bool,
+    id_transform: &'static dyn ToId,
+    manifest: Option<sync::Arc<HashSet<PathBuf>>>,
+    anchor_index: Option<sync::Arc<AnchorIndex>>,
+    dns_only: bool,
+    check_fragments: bool,
+    deadline: Option<Duration>,
+    prefer_https: bool,
+    unify_404: bool,
+    wayback_api: Option<Url>,
+    allow_redirect_to_https: bool,
+    allow_same_host_redirect: bool,
+    offline: bool,
+    warn_example_hosts: bool,
+    pins: HashMap<Url, String>,
+    expect_present: Vec<Regex>,
+    expect_absent: Vec<Regex>,
+    text_fragments: bool,
+    index_files: Vec<String>,
+    allowed_schemes: HashSet<String>,
+    max_redirects: usize,
+    charset_fallback: &'static Encoding,
 }
 
-impl Client {
-    pub fn new_no_follow() -> Self {
+/// Builder for the HTTP configuration of a `Client`.
+pub struct ClientBuilder {
+    follow: bool,
+    user_agent: String,
+    headers: HeaderMap,
+    host_headers: HashMap<String, HeaderMap>,
+    proxy: Option<reqwest::Proxy>,
+    insecure: bool,
+    ca_cert: Option<reqwest::Certificate>,
+    timeouts: Timeouts,
+    max_redirects: usize,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        ClientBuilder {
+            follow: false,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            headers: HeaderMap::new(),
+            host_headers: HashMap::new(),
+            proxy: None,
+            insecure: false,
+            ca_cert: None,
+            timeouts: Timeouts::default(),
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// Follows HTTP redirects instead of reporting them.
+    pub fn follow_redirects(mut self, follow: bool) -> Self {
+        self.follow = follow;
+        self
+    }
+
+    /// Sets the User-Agent header of HTTP requests.
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Sets the Accept header of HTTP requests, `DEFAULT_ACCEPT` by default.
+    pub fn accept(self, accept: HeaderValue) -> Self {
+        self.header(ACCEPT, accept)
+    }
+
+    /// Adds a header to all HTTP requests.
+    pub fn header<K: IntoHeaderName>(mut self, name: K, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Adds a header to HTTP requests for URLs with the given host.
+    ///
+    /// The value is marked as sensitive, so that it's never included in debug
+    /// output. It overrides any header of the same name added by `header`.
+    pub fn host_header<K: IntoHeaderName>(
+        mut self,
+        host: &str,
+        name: K,
+        mut value: HeaderValue,
+    ) -> Self {
+        value.set_sensitive(true);
+        self.host_headers
+            .entry(host.to_lowercase())
+            .or_default()
+            .insert(name, value);
+        self
+    }
+
+    /// Sends HTTP requests through a proxy.
+    pub fn proxy(mut self, proxy: Option<reqwest::Proxy>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Accepts invalid TLS certificates.
+    pub fn insecure(mut self, insecure: bool) -> Self {
+        self.insecure = insecure;
+        self
+    }
+
+    /// Trusts an additional root certificate.
+    pub fn ca_cert(mut self, ca_cert: Option<reqwest::Certificate>) -> Self {
+        self.ca_cert = ca_cert;
+        self
+    }
+
+    /// Sets the time limit for a whole request.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.read = Some(timeout);
+        self
+    }
+
+    /// Sets all time limits at once.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Gives up following redirects, including meta refresh redirects, after
+    /// this many in a row.
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Builds the client; Options unrelated to HTTP are set on the client itself.
+    pub fn build(self) -> Client {
         let redirects = sync::Arc::new(sync::Mutex::new(vec![]));
         let redirects_clone = redirects.clone();
-        let inner = reqwest::blocking::Client::builder()
-            .user_agent("linky")
+        let stopped_at = sync::Arc::new(sync::Mutex::new(None));
+        let stopped_at_clone = stopped_at.clone();
+        let origin = sync::Arc::new(sync::Mutex::new(None));
+        let origin_clone = origin.clone();
+        let header_hosts: HashSet<String> = self.host_headers.keys().cloned().collect();
+        let max_redirects = self.max_redirects;
+        let mut headers = self.headers;
+        headers
+            .entry(ACCEPT)
+            .or_insert(HeaderValue::from_static(DEFAULT_ACCEPT));
+        let mut builder = reqwest::blocking::Client::builder()
+            .gzip(true)
+            .deflate(true)
+            .brotli(true)
+            .danger_accept_invalid_certs(self.insecure)
+            .default_headers(headers);
+        if let Some(proxy) = self.proxy {
+            builder = builder.proxy(proxy);
+        }
+        if let Some(ca_cert) = self.ca_cert {
+            builder = builder.add_root_certificate(ca_cert);
+        }
+        if let Some(connect) = self.timeouts.connect {
+            builder = builder.connect_timeout(connect);
+        }
+        if let Some(read) = self.timeouts.read {
+            builder = builder.timeout(read);
+        }
+        let inner = builder
+            .user_agent(self.user_agent)
             .redirect(reqwest::redirect::Policy::custom(move |attempt| {
                 let mut redirects_guard = redirects_clone.lock().unwrap();
+                // Redirects stopped at are followed by another request, so
+                // the whole chain is checked rather than just this request
+                let is_loop = attempt.previous().contains(attempt.url())
+                    || redirects_guard.iter().any(|(_, url)| url == attempt.url())
+                    || origin_clone.lock().unwrap().as_ref() == Some(attempt.url());
                 redirects_guard.push((attempt.status(), attempt.url().clone()));
-                reqwest::redirect::Policy::default().redirect(attempt)
+                let from = attempt.previous().last().and_then(|url| url.host_str());
+                let to = attempt.url().host_str();
+                let has_host_headers = |host: Option<&str>| {
+                    host.is_some_and(|host| header_hosts.contains(&host.to_lowercase()))
+                };
+                if is_loop {
+                    attempt.error("redirect loop")
+                } else if redirects_guard.len() > max_redirects {
+                    attempt.error("too many redirects")
+                } else if from != to && (has_host_headers(from) || has_host_headers(to)) {
+                    *stopped_at_clone.lock().unwrap() = Some(attempt.url().clone());
+                    attempt.stop()
+                } else {
+                    attempt.follow()
+                }
             }))
             .build()
             .unwrap();
-        Client { inner, redirects }
+        Client {
+            inner,
+            host_headers: self.host_headers,
+            follow: self.follow,
+            redirects,
+            stopped_at,
+            origin,
+            accepted_statuses: HashSet::new(),
+            warn_permanent_redirects: false,
+            case_sensitive_paths: false,
+            max_body_bytes: None,
+            warn_duplicate_anchors: false,
+            id_transform: &GithubId,
+            manifest: None,
+            anchor_index: None,
+            dns_only: false,
+            check_fragments: true,
+            deadline: self.timeouts.deadline,
+            prefer_https: false,
+            unify_404: false,
+            wayback_api: None,
+            allow_redirect_to_https: false,
+            allow_same_host_redirect: false,
+            offline: false,
+            warn_example_hosts: false,
+            pins: HashMap::new(),
+            expect_present: vec![],
+            expect_absent: vec![],
+            text_fragments: false,
+            index_files: vec![],
+            allowed_schemes: HashSet::new(),
+            max_redirects,
+            charset_fallback: encoding_rs::UTF_8,
+        }
     }
+}
 
-    pub fn new_follow() -> Self {
-        let redirects = sync::Arc::new(sync::Mutex::new(vec![]));
-        let inner = reqwest::blocking::Client::builder()
-            .user_agent("linky")
-            .build()
-            .unwrap();
+impl Client {
+    /// Returns a builder for a client that doesn't follow redirects by default.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    /// Treats responses with the given statuses as successful.
+    pub fn accept_statuses<I: IntoIterator<Item = reqwest::StatusCode>>(
+        mut self,
+        statuses: I,
+    ) -> Self {
+        self.accepted_statuses.extend(statuses);
+        self
+    }
+
+    /// Reports links resolved via permanent redirects as PERM_REDIRECT.
+    pub fn warn_permanent_redirects(mut self, warn: bool) -> Self {
+        self.warn_permanent_redirects = warn;
+        self
+    }
+
+    /// Reports local links matching files only case-insensitively as WRONG_CASE.
+    pub fn case_sensitive_paths(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive_paths = case_sensitive;
+        self
+    }
+
+    /// Reads at most `limit` bytes of each response body when looking for anchors.
+    pub fn max_body_bytes(mut self, limit: Option<u64>) -> Self {
+        self.max_body_bytes = limit;
+        self
+    }
 
-        Client { inner, redirects }
+    /// Reports local links to files missing from the manifest as NOT_PUBLISHED.
+    pub fn manifest(mut self, manifest: Option<sync::Arc<HashSet<PathBuf>>>) -> Self {
+        self.manifest = manifest;
+        self
+    }
+
+    /// Resolves local links to documents in the index without reading them again.
+    pub fn anchor_index(mut self, anchor_index: Option<sync::Arc<AnchorIndex>>) -> Self {
+        self.anchor_index = anchor_index;
+        self
+    }
+
+    /// Reports fragments matching the first of several identical headings as DUP_ANCHOR.
+    pub fn warn_duplicate_anchors(mut self, warn: bool) -> Self {
+        self.warn_duplicate_anchors = warn;
+        self
+    }
+
+    /// Generates anchors from Markdown headings according to the given style.
+    pub fn anchor_style(mut self, anchor_style: AnchorStyle) -> Self {
+        self.id_transform = anchor_style.id_transform();
+        self
+    }
+
+    /// Generates anchors from Markdown headings using a custom transform.
+    pub fn id_transform(mut self, id_transform: &'static dyn ToId) -> Self {
+        self.id_transform = id_transform;
+        self
+    }
+
+    /// Only resolves the hosts of URL links instead of fetching them; Fragments of URL links are not checked.
+    pub fn dns_only(mut self, dns_only: bool) -> Self {
+        self.dns_only = dns_only;
+        self
+    }
+
+    /// Checks only the existence of URL links when disabled, using HEAD requests.
+    pub fn check_fragments(mut self, check_fragments: bool) -> Self {
+        self.check_fragments = check_fragments;
+        self
+    }
+
+    /// Reports `http:` links whose `https:` equivalents are reachable too as INSECURE.
+    pub fn prefer_https(mut self, prefer_https: bool) -> Self {
+        self.prefer_https = prefer_https;
+        self
+    }
+
+    /// Reports remote 404 and 410 responses as NO_DOC, like missing local files.
+    pub fn unify_404(mut self, unify_404: bool) -> Self {
+        self.unify_404 = unify_404;
+        self
+    }
+
+    /// Looks up dead remote links with the Wayback Machine availability API at
+    /// `api`, e.g. `WAYBACK_API`, and reports them as ARCHIVED if there is a
+    /// snapshot.
+    ///
+    /// Links are dead if they respond with 404 or 410, or if their hosts can't
+    /// be resolved.
+    pub fn wayback_fallback(mut self, api: Option<Url>) -> Self {
+        self.wayback_api = api;
+        self
+    }
+
+    /// Accepts redirects from `http:` to the same URL with `https:` when not
+    /// following redirects.
+    pub fn allow_redirect_to_https(mut self, allow_redirect_to_https: bool) -> Self {
+        self.allow_redirect_to_https = allow_redirect_to_https;
+        self
+    }
+
+    /// Accepts redirects within the same host when not following redirects,
+    /// unless they downgrade from `https:` to `http:`.
+    pub fn allow_same_host_redirect(mut self, allow_same_host_redirect: bool) -> Self {
+        self.allow_same_host_redirect = allow_same_host_redirect;
+        self
+    }
+
+    /// Reports URL links that require network access as SKIPPED without attempting them.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Reports URL links to reserved example and test hosts as EXAMPLE_HOST without fetching them.
+    pub fn warn_example_hosts(mut self, warn: bool) -> Self {
+        self.warn_example_hosts = warn;
+        self
+    }
+
+    /// Keeps the text content of fetched documents for checking `:~:text=` fragments.
+    pub fn text_fragments(mut self, text_fragments: bool) -> Self {
+        self.text_fragments = text_fragments;
+        self
+    }
+
+    /// Verifies the bodies of these URLs against their hex encoded SHA-256 checksums.
+    pub fn pins<I: IntoIterator<Item = (Url, String)>>(mut self, pins: I) -> Self {
+        self.pins.extend(pins.into_iter().map(|(mut url, sha256)| {
+            url.set_fragment(None);
+            (url, sha256.to_ascii_lowercase())
+        }));
+        self
+    }
+
+    /// Reports remote HTML and Markdown documents whose text doesn't match all of these regexes as CONTENT_MISMATCH.
+    pub fn expect_present<I: IntoIterator<Item = Regex>>(mut self, patterns: I) -> Self {
+        self.expect_present.extend(patterns);
+        self
+    }
+
+    /// Reports remote HTML and Markdown documents whose text matches any of these regexes as CONTENT_MISMATCH.
+    pub fn expect_absent<I: IntoIterator<Item = Regex>>(mut self, patterns: I) -> Self {
+        self.expect_absent.extend(patterns);
+        self
+    }
+
+    /// Resolves local links to directories to the first of these files that exists in the directory.
+    pub fn index_files<I: IntoIterator<Item = String>>(mut self, names: I) -> Self {
+        self.index_files.extend(names);
+        self
+    }
+
+    /// Treats URL links with the given schemes as successful without checking them.
+    pub fn allow_schemes<I: IntoIterator<Item = String>>(mut self, schemes: I) -> Self {
+        self.allowed_schemes
+            .extend(schemes.into_iter().map(|scheme| scheme.to_lowercase()));
+        self
+    }
+
+    /// Decodes documents using this encoding when their charset can't be determined.
+    pub fn charset_fallback(mut self, encoding: &'static Encoding) -> Self {
+        self.charset_fallback = encoding;
+        self
+    }
+
+    fn checks_fragments(&self, link: &Link) -> bool {
+        match *link {
+            Link::Path(_) => true,
+            Link::Url(ref url) => {
+                self.check_fragments
+                    && !self.dns_only
+                    && !self.allowed_schemes.contains(url.scheme())
+            }
+        }
+    }
+
+    fn check_duplicate_anchor(&self, document: &Document, fragment: &Option<String>) -> Result<()> {
+        match *fragment {
+            Some(ref fragment)
+                if self.warn_duplicate_anchors && document.duplicate_anchors.contains(fragment) =>
+            {
+                // Later headings with the same text get numbered anchors
+                let siblings: Vec<_> = (1..)
+                    .map(|repetition| format!("{fragment}-{repetition}"))
+                    .take_while(|id| document.ids.contains(id.as_str()))
+                    .map(|id| format!("#{id}"))
+                    .collect();
+                Err(Tag::DuplicateAnchor
+                    .as_error()
+                    .context("fragment", format!("#{fragment}"))
+                    .context("siblings", siblings.join(" ")))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_redirects(&self, document: &Document) -> Result<()> {
+        match document.redirects.first() {
+            Some(&(status, _))
+                if self.warn_permanent_redirects
+                    && (status == reqwest::StatusCode::MOVED_PERMANENTLY
+                        || status == reqwest::StatusCode::PERMANENT_REDIRECT) =>
+            {
+                Err(redirect_error(
+                    Tag::PermanentRedirect.as_error(),
+                    &document.redirects,
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_content(&self, document: &Document) -> Result<()> {
+        if document.uncheckable.is_some() || !self.check_fragments {
+            return Ok(());
+        }
+        if let Some(pattern) = self
+            .expect_present
+            .iter()
+            .find(|pattern| !pattern.is_match(document.text.as_deref().unwrap_or_default()))
+        {
+            return Err(Tag::ContentMismatch
+                .as_error()
+                .context("missing", pattern.to_string()));
+        }
+        if let Some(pattern) = self
+            .expect_absent
+            .iter()
+            .find(|pattern| pattern.is_match(document.text.as_deref().unwrap_or_default()))
+        {
+            return Err(Tag::ContentMismatch
+                .as_error()
+                .context("present", pattern.to_string()));
+        }
+        Ok(())
+    }
+
+    fn check_https(&self, document: &Document) -> Result<()> {
+        match document.https_url {
+            Some(ref https_url) => Err(Tag::InsecureLink
+                .as_error()
+                .context("https", https_url.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn https_equivalent(&self, url: &Url) -> Option<Url> {
+        if url.scheme() != "http" {
+            return None;
+        }
+        let mut https_url = url.clone();
+        https_url.set_scheme("https").ok()?;
+        match self.head(https_url.as_str()) {
+            Ok((response, redirects))
+                if self.is_accepted(response.status()) && (self.follow || redirects.is_empty()) =>
+            {
+                Some(https_url)
+            }
+            _ => None,
+        }
+    }
+
+    fn is_accepted(&self, status: reqwest::StatusCode) -> bool {
+        status.is_success() || self.accepted_statuses.contains(&status)
     }
 
     pub fn get<U: reqwest::IntoUrl>(
@@ -234,15 +1180,67 @@ impl Client {
     ) -> reqwest::Result<(
         reqwest::blocking::Response,
         Vec<(reqwest::StatusCode, reqwest::Url)>,
+    )> {
+        self.send(self.get_request(url))
+    }
+
+    fn get_request<U: reqwest::IntoUrl>(&self, url: U) -> reqwest::blocking::RequestBuilder {
+        self.inner.get(url)
+    }
+
+    /// Like `get` but without fetching the response body.
+    pub fn head<U: reqwest::IntoUrl>(
+        &self,
+        url: U,
+    ) -> reqwest::Result<(
+        reqwest::blocking::Response,
+        Vec<(reqwest::StatusCode, reqwest::Url)>,
+    )> {
+        self.send(self.inner.head(url))
+    }
+
+    fn send(
+        &self,
+        request: reqwest::blocking::RequestBuilder,
+    ) -> reqwest::Result<(
+        reqwest::blocking::Response,
+        Vec<(reqwest::StatusCode, reqwest::Url)>,
     )> {
         self.redirects.lock().unwrap().clear();
-        let response = self
-            .inner
-            .get(url)
-            .header(ACCEPT, "text/html,application/xhtml+xml")
-            .send()?;
-        let redirects = self.redirects.lock().unwrap().clone();
-        Ok((response, redirects))
+        self.stopped_at.lock().unwrap().take();
+        let mut request = request.build()?;
+        *self.origin.lock().unwrap() = Some(request.url().clone());
+        // Headers of the request itself, like validators, are kept for every
+        // hop, unlike the host headers
+        let mut headers = request.headers().clone();
+        for name in self.host_headers.values().flat_map(HeaderMap::keys) {
+            headers.remove(name);
+        }
+        loop {
+            let host_headers = request
+                .url()
+                .host_str()
+                .and_then(|host| self.host_headers.get(&host.to_lowercase()));
+            if let Some(headers) = host_headers {
+                request.headers_mut().extend(headers.clone());
+            }
+            let method = request.method().clone();
+            let timeout = request.timeout().copied();
+            let response = self.inner.execute(request)?;
+            // Host headers must not leak to other hosts, so reqwest stops at
+            // redirects between hosts and they are followed from scratch here
+            match self.stopped_at.lock().unwrap().take() {
+                Some(target) => {
+                    request = self.inner.request(method, target).build()?;
+                    *request.headers_mut() = headers.clone();
+                    *request.timeout_mut() = timeout;
+                }
+                None => {
+                    let redirects = self.redirects.lock().unwrap().clone();
+                    return Ok((response, redirects));
+                }
+            }
+        }
     }
 
     pub fn fetch_link<'a>(
@@ -251,61 +1249,559 @@ impl Client {
         link: &Link,
     ) -> result::Result<Document<'a>, sync::Arc<Error>> {
         match *link {
-            Link::Path(ref path) => Self::fetch_local(path.as_ref(), urldecode),
-            Link::Url(ref url) => self.fetch_remote(url),
+            Link::Path(ref path) => self.fetch_local(path.as_ref(), urldecode),
+            Link::Url(ref url) if self.allowed_schemes.contains(url.scheme()) => {
+                Ok(Document::empty())
+            }
+            Link::Url(ref url) if self.warn_example_hosts && is_example_host(url) => {
+                Err(Tag::ExampleHost
+                    .as_error()
+                    .context("host", url.host_str().unwrap_or_default().to_string()))
+            }
+            Link::Url(ref url) if url.scheme() == "tel" || url.scheme() == "sms" => {
+                check_phone_numbers(url).map(|()| Document::empty())
+            }
+            Link::Url(_) if self.offline => Err(Tag::Skipped.as_error()),
+            Link::Url(ref url) if self.dns_only => self
+                .resolve_host(url)
+                .or_else(|err| self.wayback_fallback_for(url, err)),
+            Link::Url(ref url) => self
+                .fetch_remote(url)
+                .or_else(|err| self.wayback_fallback_for(url, err)),
+        }
+        .map_err(|err| sync::Arc::new(err.context("link", link.to_string())))
+    }
+
+    /// Like `fetch_link`, but returns `cached` instead if the server answers a
+    /// conditional request with 304 Not Modified.
+    ///
+    /// Documents without an `ETag` or `Last-Modified` validator are fetched
+    /// again in full, and so are local documents.
+    pub fn revalidate<'a>(
+        &self,
+        urldecode: bool,
+        link: &Link,
+        cached: &Document<'a>,
+    ) -> result::Result<Document<'a>, sync::Arc<Error>> {
+        if let Link::Url(ref url) = *link {
+            match self.is_not_modified(url, cached) {
+                Ok(true) => return Ok(cached.clone()),
+                Ok(false) => (),
+                Err(err) => debug!("revalidating {} failed: {}", url, err),
+            }
+        }
+        self.fetch_link(urldecode, link)
+    }
+
+    fn is_not_modified(&self, url: &Url, cached: &Document) -> Result<bool> {
+        if cached.etag.is_none() && cached.last_modified.is_none() {
+            return Ok(false);
+        }
+        let mut request = self.inner.head(url.as_str());
+        if let Some(ref etag) = cached.etag {
+            request = request.header(IF_NONE_MATCH, etag.as_str());
         }
-        .map_err(|err| sync::Arc::new(err.context(Cow::from(format!("link = {link}")))))
+        if let Some(ref last_modified) = cached.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+        }
+        let (response, _) = self.send(request)?;
+        Ok(response.status() == reqwest::StatusCode::NOT_MODIFIED)
     }
 
-    fn fetch_local<'b>(path: &Path, urldecode: bool) -> Result<Document<'b>> {
+    fn fetch_local<'b>(&self, path: &Path, urldecode: bool) -> Result<Document<'b>> {
+        // Indexed documents are kept without their text content
+        let indexed = self
+            .anchor_index
+            .as_ref()
+            .filter(|_| !self.text_fragments)
+            .and_then(|index| index.get(path));
         if path.is_relative() {
             Err(Tag::Absolute.as_error())
+        } else if let Some(document) = indexed {
+            self.check_local(path)?;
+            Ok(document)
         } else if path.is_dir() {
-            Err(Tag::Directory.as_error())
+            let index = self
+                .index_files
+                .iter()
+                .map(|name| path.join(name))
+                .find(|index| index.is_file());
+            match index {
+                Some(index) => self.fetch_local(&index, urldecode),
+                None => Err(Tag::Directory.as_error()),
+            }
         } else {
-            let reader = File::open(path).or_else(|e| {
+            let reader = self.open_local(path).or_else(|e| {
                 if urldecode {
                     urlencoding::decode(path.to_str().unwrap())
                         .map_err(|_| e)
-                        .and_then(File::open)
+                        .and_then(|path| self.open_local(Path::new(&path)))
                 } else {
                     Err(e)
                 }
             })?;
-            Document::parse(reader, &MARKDOWN_CONTENT_TYPE)
+            if is_html(path) {
+                self.parse(reader, &mime::TEXT_HTML_UTF_8)
+            } else if is_asciidoc(path) {
+                self.parse(reader, &ASCIIDOC_CONTENT_TYPE)
+            } else {
+                self.parse(reader, &MARKDOWN_CONTENT_TYPE)
+            }
         }
     }
 
-    fn fetch_remote<'b>(&self, url: &Url) -> Result<Document<'b>> {
-        if url.scheme() != "http" && url.scheme() != "https" {
-            return Err(Tag::Protocol.as_error());
-        }
+    /// Parses a document, keeping only the forms of its text content that are checked.
+    fn parse<'a, R: Read>(&self, reader: R, content_type: &mime::Mime) -> Result<Document<'a>> {
+        let keep_text = KeepText {
+            original: !self.expect_present.is_empty() || !self.expect_absent.is_empty(),
+            lowercase: self.text_fragments,
+        };
+        Document::parse_with(
+            reader,
+            content_type,
+            self.id_transform,
+            self.charset_fallback,
+            keep_text,
+        )
+    }
 
-        let (response, redirects) = self.get(url.as_str())?;
+    fn open_local(&self, path: &Path) -> Result<File> {
+        self.check_local(path)?;
+        Ok(File::open(path)?)
+    }
 
-        if !response.status().is_success() {
-            return Err(Tag::HttpStatus(response.status()).as_error());
+    fn check_local(&self, path: &Path) -> Result<()> {
+        if let Some(ref manifest) = self.manifest {
+            if !manifest.contains(path) {
+                return Err(Tag::NotPublished.as_error());
+            }
         }
-        if !redirects.is_empty() {
-            let mut err: Error = Tag::HttpStatus(redirects[0].0).as_error();
-            for &(status, ref url) in redirects.iter().rev() {
-                err = err.context(Cow::from(format!(
-                    "redirect({}) = {}",
-                    status.as_u16(),
-                    url
-                )));
+        if self.case_sensitive_paths {
+            check_case(path)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the error of a dead link with ARCHIVED if the Wayback Machine
+    /// has a snapshot of it.
+    fn wayback_fallback_for<'b>(&self, url: &Url, err: Error) -> Result<Document<'b>> {
+        let api = match self.wayback_api {
+            Some(ref api) if is_dead_link(url, &err) => api,
+            _ => return Err(err),
+        };
+        let mut query = api.clone();
+        query.query_pairs_mut().append_pair("url", url.as_str());
+        match self.wayback_snapshot(&query) {
+            Ok(Some(snapshot)) => Err(Tag::ArchivedAvailable
+                .as_error()
+                .context("tag", err.tag.to_string())
+                .context("snapshot", snapshot)),
+            Ok(None) => Err(err),
+            Err(lookup_err) => {
+                debug!("wayback lookup for {} failed: {}", url, lookup_err);
+                Err(err)
             }
-            return Err(err);
         }
-        let content_type: Result<HeaderValue> = response
-            .headers()
+    }
+
+    fn wayback_snapshot(&self, query: &Url) -> Result<Option<String>> {
+        let (response, _) = self.get(query.as_str())?;
+        if !response.status().is_success() {
+            return Err(Tag::HttpStatus(response.status()).as_error());
+        }
+        let body: serde_json::Value = serde_json::from_str(&response.text()?)
+            .map_err(|err| Error::decoding_error(Cow::from(err.to_string())))?;
+        let closest = &body["archived_snapshots"]["closest"];
+        Ok(closest["url"]
+            .as_str()
+            .filter(|_| closest["available"] != false)
+            .map(str::to_string))
+    }
+
+    fn resolve_host<'b>(&self, url: &Url) -> Result<Document<'b>> {
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(Tag::Protocol.as_error());
+        }
+        match url.socket_addrs(|| None) {
+            Ok(ref addrs) if !addrs.is_empty() => Ok(Document::empty()),
+            Ok(_) => Err(Tag::DnsError
+                .as_error()
+                .context("host", url.host_str().unwrap_or_default().to_string())),
+            Err(err) => Err(Tag::DnsError
+                .as_error()
+                .context("host", url.host_str().unwrap_or_default().to_string())
+                .context("reason", err.to_string())),
+        }
+    }
+
+    fn fetch_remote<'b>(&self, url: &Url) -> Result<Document<'b>> {
+        let mut redirects = vec![];
+        let mut current = url.clone();
+        let deadline = self.deadline.map(|limit| Instant::now() + limit);
+        loop {
+            let mut document =
+                self.fetch_remote_once(&current, deadline)
+                    .map_err(|err| match deadline {
+                        Some(deadline) if err.tag == Tag::Timeout && Instant::now() >= deadline => {
+                            deadline_error()
+                        }
+                        _ => err,
+                    })?;
+            let final_url = document.final_url.take().unwrap_or_else(|| current.clone());
+            redirects.append(&mut document.redirects);
+            let target = document
+                .refresh
+                .as_ref()
+                .and_then(|refresh| document.base_url(&final_url).join(refresh).ok());
+            match target {
+                Some(target) if !self.follow => {
+                    return Err(Tag::MetaRefresh
+                        .as_error()
+                        .context("refresh", target.to_string()));
+                }
+                Some(target) => {
+                    redirects.push((reqwest::StatusCode::OK, target.clone()));
+                    if is_redirect_loop(url, &redirects) {
+                        return Err(redirect_error(Tag::RedirectLoop.as_error(), &redirects));
+                    }
+                    if redirects.len() > self.max_redirects {
+                        return Err(redirect_error(
+                            Tag::TooManyRedirects
+                                .as_error()
+                                .context("limit", self.max_redirects.to_string()),
+                            &redirects,
+                        ));
+                    }
+                    current = target;
+                }
+                None => {
+                    if !redirects.is_empty() {
+                        document.final_url = Some(final_url);
+                    }
+                    document.redirects = redirects;
+                    self.check_content(&document)?;
+                    if self.prefer_https {
+                        document.https_url = self.https_equivalent(url);
+                    }
+                    return Ok(document);
+                }
+            }
+        }
+    }
+
+    fn is_benign_redirect_chain(
+        &self,
+        url: &Url,
+        redirects: &[(reqwest::StatusCode, reqwest::Url)],
+    ) -> bool {
+        let is_benign = |from: &Url, to: &Url| {
+            (self.allow_same_host_redirect && is_benign_redirect(from, to, false))
+                || (self.allow_redirect_to_https && is_benign_redirect(from, to, true))
+        };
+        iter::once(url)
+            .chain(redirects.iter().map(|(_, url)| url))
+            .zip(redirects.iter().map(|(_, url)| url))
+            .all(|(from, to)| is_benign(from, to))
+    }
+
+    fn fetch_remote_once<'b>(&self, url: &Url, deadline: Option<Instant>) -> Result<Document<'b>> {
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(Tag::Protocol.as_error());
+        }
+
+        let remaining = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if remaining > Duration::ZERO => Some(remaining),
+                _ => return Err(deadline_error()),
+            },
+            None => None,
+        };
+        let send = |request: reqwest::blocking::RequestBuilder| match remaining {
+            Some(remaining) => self.send(request.timeout(remaining)),
+            None => self.send(request),
+        };
+        let pin = self.pins.get(url);
+        let result = if self.check_fragments || pin.is_some() {
+            send(self.get_request(url.as_str()))
+        } else {
+            send(self.inner.head(url.as_str())).and_then(|(response, redirects)| {
+                if response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED {
+                    send(self.get_request(url.as_str()))
+                } else {
+                    Ok((response, redirects))
+                }
+            })
+        };
+        let (response, redirects) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                let redirects = self.redirects.lock().unwrap().clone();
+                if err.is_redirect() && is_redirect_loop(url, &redirects) {
+                    return Err(redirect_error(Tag::RedirectLoop.as_error(), &redirects));
+                }
+                if err.is_redirect() {
+                    return Err(redirect_error(
+                        Tag::TooManyRedirects
+                            .as_error()
+                            .context("limit", self.max_redirects.to_string()),
+                        &redirects,
+                    ));
+                }
+                return Err(err.into());
+            }
+        };
+
+        if !self.is_accepted(response.status()) {
+            let status = response.status();
+            if self.unify_404
+                && (status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::GONE)
+            {
+                return Err(Tag::NoDocument
+                    .as_error()
+                    .context("status", status.as_u16().to_string()));
+            }
+            return Err(Tag::HttpStatus(status).as_error());
+        }
+        if !self.follow
+            && !redirects.is_empty()
+            && !self.accepted_statuses.contains(&redirects[0].0)
+            && !self.is_benign_redirect_chain(url, &redirects)
+        {
+            return Err(redirect_error(
+                Tag::HttpStatus(redirects[0].0).as_error(),
+                &redirects,
+            ));
+        }
+        let final_url = if redirects.is_empty() {
+            None
+        } else {
+            Some(response.url().clone())
+        };
+        if !self.check_fragments && pin.is_none() {
+            return Ok(Document {
+                final_url,
+                redirects,
+                ..Document::empty()
+            });
+        }
+        // Supported encodings are decoded by reqwest, which then removes the header
+        if let Some(encoding) = response.headers().get(CONTENT_ENCODING) {
+            if encoding != "identity" {
+                return Err(Tag::DecodingError
+                    .as_error()
+                    .context("content-encoding", encoding.to_str()?.to_string()));
+            }
+        }
+        let headers = response.headers().clone();
+        let mut response: Box<dyn Read> = Box::new(response);
+        if let Some(expected) = pin {
+            let mut body = vec![];
+            response.read_to_end(&mut body)?;
+            check_checksum(&body, expected)?;
+            if !self.check_fragments {
+                return Ok(Document {
+                    final_url,
+                    redirects,
+                    bytes_read: Some(body.len() as u64),
+                    ..Document::empty()
+                });
+            }
+            response = Box::new(Cursor::new(body));
+        }
+        let content_type: Result<HeaderValue> = headers
             .get(CONTENT_TYPE)
             .cloned()
             .ok_or_else(|| Tag::NoMime.as_error());
-        let content_type: mime::Mime = content_type?.to_str()?.parse()?;
+        let content_type = parse_content_type(content_type?.to_str()?)?;
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(std::string::ToString::to_string)
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        let mut body = vec![];
+        match self.max_body_bytes {
+            Some(limit) => response.take(limit + 1).read_to_end(&mut body)?,
+            None => response.read_to_end(&mut body)?,
+        };
+        let bytes_read = body.len() as u64;
+        let truncated_at = self.max_body_bytes.filter(|&limit| bytes_read > limit);
+        if let Some(limit) = truncated_at {
+            body.truncate(limit as usize);
+        }
+        let mut document = self.parse(Cursor::new(body), &content_type)?;
+        document.truncated_at = truncated_at;
+        document.bytes_read = Some(bytes_read);
+        document.lines = None;
+        document.final_url = final_url;
+        document.redirects = redirects;
+        document.etag = etag;
+        document.last_modified = last_modified;
+        Ok(document)
+    }
+}
+
+fn check_checksum(body: &[u8], expected: &str) -> Result<()> {
+    let actual = format!("{:x}", Sha256::digest(body));
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(Tag::ChecksumMismatch
+            .as_error()
+            .context("expected", expected.to_string())
+            .context("actual", actual))
+    }
+}
+
+fn deadline_error() -> Error {
+    Tag::Timeout.as_error().context("phase", "deadline")
+}
+
+/// Top-level and second-level domains reserved for examples and testing by RFC 2606 and RFC 6761.
+const EXAMPLE_TLDS: &[&str] = &["example", "test", "invalid", "localhost"];
+const EXAMPLE_DOMAINS: &[&str] = &["example.com", "example.net", "example.org"];
+
+fn is_example_host(url: &Url) -> bool {
+    let host = match url.domain() {
+        Some(host) => host.trim_end_matches('.').to_ascii_lowercase(),
+        None => return false,
+    };
+    let is_within = |domain: &str| {
+        host == domain
+            || host
+                .strip_suffix(domain)
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    };
+    EXAMPLE_TLDS
+        .iter()
+        .chain(EXAMPLE_DOMAINS)
+        .any(|domain| is_within(domain))
+}
+
+/// Checks the structure of the numbers of `tel:` (RFC 3966) and `sms:` (RFC 5724) URLs.
+fn check_phone_numbers(url: &Url) -> Result<()> {
+    let path = url.path();
+    let valid = if url.scheme() == "sms" {
+        path.split(',').all(is_phone_number)
+    } else {
+        is_phone_number(path)
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(Tag::InvalidTel
+            .as_error()
+            .context("number", path.to_string()))
+    }
+}
+
+fn is_phone_number(subscriber: &str) -> bool {
+    let mut parts = subscriber.split(';');
+    let number = parts.next().unwrap_or_default();
+    let (digits, global) = match number.strip_prefix('+') {
+        Some(digits) => (digits, true),
+        None => (number, false),
+    };
+    let is_digit = |c: char| {
+        if global {
+            c.is_ascii_digit()
+        } else {
+            c.is_ascii_hexdigit() || c == '*' || c == '#'
+        }
+    };
+    let is_separator = |c: char| matches!(c, '-' | '.' | '(' | ')');
+    let mut params = parts.map(|param| param.split('=').next().unwrap_or_default());
+    digits.chars().any(is_digit)
+        && digits.chars().all(|c| is_digit(c) || is_separator(c))
+        && (global || params.any(|name| name.eq_ignore_ascii_case("phone-context")))
+}
+
+fn check_case(path: &Path) -> Result<()> {
+    let mut dir = PathBuf::new();
+    for component in path.components() {
+        if let std::path::Component::Normal(name) = component {
+            let parent = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir.as_path()
+            };
+            let entries = match fs::read_dir(parent) {
+                Ok(entries) => entries,
+                Err(_) => return Ok(()),
+            };
+            let names: Vec<_> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.file_name()))
+                .collect();
+            if !names.iter().any(|entry| entry == name) {
+                let name = name.to_string_lossy().to_lowercase();
+                return match names
+                    .iter()
+                    .find(|entry| entry.to_string_lossy().to_lowercase() == name)
+                {
+                    Some(actual) => Err(Tag::WrongCase
+                        .as_error()
+                        .context("actual", dir.join(actual).display().to_string())),
+                    None => Ok(()),
+                };
+            }
+        }
+        dir.push(component);
+    }
+    Ok(())
+}
+
+fn meta_refresh_target(attributes: &str) -> Option<String> {
+    let mut is_refresh = false;
+    let mut content = None;
+    for (_, attr) in htmlstream::attr_iter(attributes) {
+        if attr.name.eq_ignore_ascii_case("http-equiv") {
+            is_refresh = attr.value.eq_ignore_ascii_case("refresh");
+        } else if attr.name == "content" {
+            content = Some(decode_html_entities(&attr.value));
+        }
+    }
+    if !is_refresh {
+        return None;
+    }
+    // The content looks like `5; url=http://example.com/`
+    let content = content?;
+    let (_, target) = content.split_once([';', ','])?;
+    let target = target.trim_start();
+    if !target.get(..3)?.eq_ignore_ascii_case("url") {
+        return None;
+    }
+    let target = target[3..].trim_start().strip_prefix('=')?.trim();
+    let target = target.trim_matches(|c| c == '\'' || c == '"');
+    if target.is_empty() {
+        None
+    } else {
+        Some(target.to_string())
+    }
+}
+
+/// Returns whether a redirect keeps the host and doesn't downgrade to `http:`,
+/// and if `upgrade_only`, only changes the scheme from `http:` to `https:`.
+fn is_benign_redirect(from: &Url, to: &Url, upgrade_only: bool) -> bool {
+    let is_upgrade = from.scheme() == "http" && to.scheme() == "https";
+    if from.host_str() != to.host_str() || (from.scheme() != to.scheme() && !is_upgrade) {
+        return false;
+    }
+    !upgrade_only || (is_upgrade && from[Position::BeforePath..] == to[Position::BeforePath..])
+}
+
+fn is_redirect_loop(url: &Url, redirects: &[(reqwest::StatusCode, reqwest::Url)]) -> bool {
+    let mut seen = HashSet::new();
+    seen.insert(url);
+    !redirects.iter().all(|(_, url)| seen.insert(url))
+}
 
-        Document::parse(response, &content_type)
+fn redirect_error(mut err: Error, redirects: &[(reqwest::StatusCode, reqwest::Url)]) -> Error {
+    for &(status, ref url) in redirects.iter().rev() {
+        err = err.context(format!("redirect({})", status.as_u16()), url.to_string());
     }
+    err
 }
 
 struct MdAnchorParser<'a> {
@@ -314,6 +1810,7 @@ struct MdAnchorParser<'a> {
     headers: &'a mut Headers,
     id_transform: &'a dyn ToId,
     header_acc: String,
+    footnote_ids: Vec<String>,
 }
 
 impl<'a> MdAnchorParser<'a> {
@@ -324,33 +1821,192 @@ impl<'a> MdAnchorParser<'a> {
             headers,
             id_transform,
             header_acc: String::new(),
+            footnote_ids: vec![],
         }
     }
 
     fn from_buffer(buffer: &'a str, id_transform: &'a dyn ToId, headers: &'a mut Headers) -> Self {
-        MdAnchorParser::new(Parser::new(buffer), id_transform, headers)
+        let (_, body) = split_front_matter(buffer);
+        let parser = Parser::new_ext(body, Options::ENABLE_FOOTNOTES);
+        MdAnchorParser::new(parser, id_transform, headers)
+    }
+}
+
+/// Elements that don't separate words in the text content of HTML documents.
+const INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "cite", "code", "data", "dfn", "em", "i", "kbd", "mark", "q",
+    "s", "samp", "small", "span", "strong", "sub", "sup", "time", "u", "var", "wbr",
+];
+
+/// Extracts the text content of a Markdown document.
+fn markdown_text(buffer: &str) -> String {
+    let (_, body) = split_front_matter(buffer);
+    let mut text = String::new();
+    for event in Parser::new(body) {
+        match event {
+            Event::Text(chunk) | Event::Code(chunk) => text.push_str(&chunk),
+            Event::End(pulldown_cmark::Tag::Emphasis)
+            | Event::End(pulldown_cmark::Tag::Strong)
+            | Event::End(pulldown_cmark::Tag::Strikethrough)
+            | Event::End(pulldown_cmark::Tag::Link(..))
+            | Event::End(pulldown_cmark::Tag::Image(..)) => (),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => text.push(' '),
+            _ => (),
+        }
+    }
+    text
+}
+
+lazy_static! {
+    static ref ASCIIDOC_SECTION: Regex =
+        Regex::new(r"^(={2,6})[ \t]+(.+?)(?:[ \t]+=+)?[ \t]*$").unwrap();
+    static ref ASCIIDOC_BLOCK_ANCHOR: Regex =
+        Regex::new(r"^(?:\[\[([\w:.-]+)(?:,[^\]]*)?\]\]|\[#([\w:.-]+)[^\]]*\])$").unwrap();
+    static ref ASCIIDOC_INLINE_ANCHOR: Regex =
+        Regex::new(r"\[\[([\w:.-]+)(?:,[^\]]*)?\]\]|anchor:([\w:.-]+)\[").unwrap();
+    static ref ASCIIDOC_ATTRIBUTE: Regex =
+        Regex::new(r"^:(idprefix|idseparator):[ \t]*(.*)$").unwrap();
+    static ref ASCIIDOC_INVALID_ID_CHARS: Regex =
+        Regex::new(r"<[^>]+>|&(?:[a-z][a-z]+\d{0,2}|#\d{2,5}|#x[\da-f]{2,4});|[^ \w.-]+").unwrap();
+}
+
+/// Collects the anchors of an AsciiDoc document the way Asciidoctor generates
+/// them: explicit ids, and section titles prefixed and separated by `_` unless
+/// the `idprefix` and `idseparator` attributes say otherwise.
+fn asciidoc_ids(buffer: &str) -> HashSet<String> {
+    let mut ids = HashSet::new();
+    let mut prefix = "_".to_string();
+    let mut separator = "_".to_string();
+    let mut has_block_anchor = false;
+    let mut delimiter: Option<&str> = None;
+    for line in buffer.lines() {
+        let line = line.trim_end();
+        if let Some(open) = delimiter {
+            if line == open {
+                delimiter = None;
+            }
+            continue;
+        }
+        if line.len() >= 4
+            && ["-", ".", "/", "+"]
+                .iter()
+                .any(|c| line == c.repeat(line.len()))
+        {
+            delimiter = Some(line);
+            continue;
+        }
+        if let Some(captures) = ASCIIDOC_ATTRIBUTE.captures(line) {
+            let value = captures[2].to_string();
+            match &captures[1] {
+                "idprefix" => prefix = value,
+                _ => separator = value,
+            }
+            continue;
+        }
+        if let Some(captures) = ASCIIDOC_BLOCK_ANCHOR.captures(line) {
+            let id = captures.get(1).or_else(|| captures.get(2)).unwrap();
+            ids.insert(id.as_str().to_string());
+            has_block_anchor = true;
+            continue;
+        }
+        if let Some(captures) = ASCIIDOC_SECTION.captures(line) {
+            if !has_block_anchor {
+                let id = asciidoc_id(&captures[2], &prefix, &separator);
+                let mut unique = id.clone();
+                let mut count = 2;
+                while ids.contains(&unique) {
+                    unique = format!("{id}{separator}{count}");
+                    count += 1;
+                }
+                ids.insert(unique);
+            }
+        }
+        for captures in ASCIIDOC_INLINE_ANCHOR.captures_iter(line) {
+            let id = captures.get(1).or_else(|| captures.get(2)).unwrap();
+            ids.insert(id.as_str().to_string());
+        }
+        has_block_anchor = false;
+    }
+    ids
+}
+
+/// Generates the id of an AsciiDoc section title.
+fn asciidoc_id(title: &str, prefix: &str, separator: &str) -> String {
+    let title = title.to_lowercase();
+    let title = ASCIIDOC_INVALID_ID_CHARS.replace_all(&title, "");
+    let mut id = prefix.to_string();
+    let mut in_separator = false;
+    for c in title.chars() {
+        if c == ' ' || c == '.' || c == '-' {
+            if !in_separator {
+                id.push_str(separator);
+            }
+            in_separator = true;
+        } else {
+            id.push(c);
+            in_separator = false;
+        }
+    }
+    if in_separator {
+        id.truncate(id.len() - separator.len());
+    }
+    if prefix.is_empty() {
+        id.trim_start_matches(separator).to_string()
+    } else {
+        id
+    }
+}
+
+fn normalize_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Splits a buffer into its front matter, if any, and the remaining body.
+///
+/// Front matter is a leading block delimited by `---` lines (YAML) or `+++`
+/// lines (TOML). YAML front matter may also be terminated by a `...` line. A
+/// leading delimiter without a matching closing delimiter is not front matter.
+fn split_front_matter(buffer: &str) -> (&str, &str) {
+    let mut lines = buffer.split_inclusive('\n');
+    let closing: &[&str] = match lines.next().map(str::trim_end) {
+        Some("---") => &["---", "..."],
+        Some("+++") => &["+++"],
+        _ => return ("", buffer),
+    };
+    let mut len = buffer.find('\n').map_or(buffer.len(), |pos| pos + 1);
+    for line in lines {
+        len += line.len();
+        if closing.contains(&line.trim_end()) {
+            return buffer.split_at(len);
+        }
     }
+    ("", buffer)
 }
 
 impl<'a> Iterator for MdAnchorParser<'a> {
     type Item = String;
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(id) = self.footnote_ids.pop() {
+            return Some(id);
+        }
         for event in self.parser.by_ref() {
             match event {
                 Event::Start(pulldown_cmark::Tag::Heading(_)) => {
                     self.is_header = true;
                 }
-                Event::Text(text) => {
-                    if self.is_header {
-                        self.header_acc.push_str(text.to_string().as_str());
+                Event::Start(pulldown_cmark::Tag::FootnoteDefinition(label)) => {
+                    self.footnote_ids = self.id_transform.footnote_ids(&label);
+                    if let Some(id) = self.footnote_ids.pop() {
+                        return Some(id);
                     }
                 }
-                Event::Code(text) => {
-                    if self.is_header {
-                        self.header_acc.push('`');
-                        self.header_acc.push_str(text.to_string().as_str());
-                        self.header_acc.push('`');
-                    }
+                Event::Text(text) if self.is_header => {
+                    self.header_acc.push_str(text.to_string().as_str());
+                }
+                Event::Code(text) if self.is_header => {
+                    self.header_acc.push('`');
+                    self.header_acc.push_str(text.to_string().as_str());
+                    self.header_acc.push('`');
                 }
                 Event::End(pulldown_cmark::Tag::Heading(_)) => {
                     self.is_header = false;
@@ -366,36 +2022,95 @@ impl<'a> Iterator for MdAnchorParser<'a> {
     }
 }
 
-fn read_chars(reader: &mut dyn Read, charset_hint: Option<String>) -> Result<String> {
+/// Decodes a document using the first recognized charset among the hint and
+/// the detected ones, falling back to `fallback` and replacing malformed sequences.
+fn read_chars(
+    reader: &mut dyn Read,
+    charset_hint: Option<String>,
+    fallback: &'static Encoding,
+) -> Result<String> {
     let mut buffer = Vec::new();
     reader.read_to_end(&mut buffer)?;
+    // Detection needs at least four bytes to sniff byte order marks
+    if buffer.len() < 4 {
+        let charset = charset_hint
+            .and_then(|hint| Encoding::for_label_no_replacement(hint.trim().as_bytes()))
+            .unwrap_or(fallback);
+        return Ok(charset.decode(&buffer).0.into_owned());
+    }
     let mut cursor = Cursor::new(buffer);
 
     let charsets = xhtmlchardet::detect(&mut cursor, charset_hint)?;
 
     debug!("detected charsets: {:?}", &charsets);
 
-    charsets
+    let charset = charsets
         .iter()
-        .flat_map(|v| Encoding::for_label_no_replacement(v.as_bytes()))
+        .flat_map(|v| Encoding::for_label_no_replacement(v.trim().as_bytes()))
         .next()
-        .map(|charset| charset.decode(cursor.into_inner().as_ref()).0.to_string())
-        .ok_or_else(|| Error::decoding_error(Cow::from("Failed to detect character encoding")))
+        .unwrap_or_else(|| {
+            debug!("falling back to charset: {}", fallback.name());
+            fallback
+        });
+    Ok(charset.decode(cursor.into_inner().as_ref()).0.to_string())
+}
+
+/// Parses a Content-Type header, dropping malformed parameters if needed.
+fn parse_content_type(value: &str) -> Result<mime::Mime> {
+    value.parse().or_else(|err| {
+        let mut parts = value
+            .split(';')
+            .map(str::trim)
+            .filter(|part| !part.is_empty());
+        let essence = parts.next().unwrap_or_default();
+        let lenient = iter::once(essence)
+            .chain(parts.filter(|part| part.contains('=')))
+            .collect::<Vec<_>>()
+            .join("; ");
+        lenient
+            .parse()
+            .or_else(|_| essence.parse())
+            .map_err(|_| Error::from(err))
+    })
 }
 
+/// Reads a local file into `buffer`, decoding UTF-16 files and stripping byte order marks.
+///
+/// Files without a byte order mark must be UTF-8.
 pub fn slurp<P: AsRef<Path>>(filename: &P, buffer: &mut String) -> io::Result<usize> {
-    File::open(filename.as_ref())?.read_to_string(buffer)
+    let bytes = fs::read(filename.as_ref())?;
+    let chars = match Encoding::for_bom(&bytes) {
+        Some((encoding, bom_len)) => encoding
+            .decode_without_bom_handling(&bytes[bom_len..])
+            .0
+            .into_owned(),
+        None => String::from_utf8(bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+    };
+    buffer.push_str(&chars);
+    Ok(chars.len())
 }
 
 lazy_static! {
     static ref GITHUB_PUNCTUATION: Regex = Regex::new(r"[^\w -]").unwrap();
+    static ref HYPHENS: Regex = Regex::new(r"-{2,}").unwrap();
 }
 
-trait ToId {
+/// A transform from Markdown heading text to anchors.
+pub trait ToId: Sync {
+    /// Returns the anchor of a heading, where `repetition` counts earlier headings with the same text.
     fn to_id(&self, text: &str, repetition: usize) -> String;
+
+    /// Returns the anchors of a footnote definition and of its first reference.
+    ///
+    /// No footnote anchors are generated by default.
+    fn footnote_ids(&self, _label: &str) -> Vec<String> {
+        vec![]
+    }
 }
 
-struct GithubId;
+/// GitHub's heading anchors.
+pub struct GithubId;
 
 impl ToId for GithubId {
     fn to_id(&self, text: &str, repetition: usize) -> String {
@@ -408,6 +2123,55 @@ impl ToId for GithubId {
             format!("{text}-{repetition}")
         }
     }
+
+    /// GitHub renders footnotes with `user-content-` prefixed anchors, but
+    /// resolves fragments without the prefix too.
+    fn footnote_ids(&self, label: &str) -> Vec<String> {
+        ["fn", "fnref"]
+            .iter()
+            .flat_map(|kind| {
+                let id = format!("{kind}-{label}");
+                vec![format!("{GITHUB_PREFIX}{id}"), id]
+            })
+            .collect()
+    }
+}
+
+/// GitLab's heading anchors, which unlike GitHub's squeeze repeated hyphens.
+pub struct GitlabId;
+
+impl ToId for GitlabId {
+    fn to_id(&self, text: &str, repetition: usize) -> String {
+        let text = GITHUB_PUNCTUATION.replace_all(text, "");
+        let text = text.to_lowercase().replace(' ', "-");
+        let text = HYPHENS.replace_all(&text, "-");
+        if repetition == 0 {
+            text.into_owned()
+        } else {
+            format!("{text}-{repetition}")
+        }
+    }
+}
+
+/// Pandoc's auto_identifiers extension.
+pub struct PandocId;
+
+impl ToId for PandocId {
+    fn to_id(&self, text: &str, repetition: usize) -> String {
+        let text: String = text
+            .chars()
+            .filter(|&c| c.is_alphanumeric() || c.is_whitespace() || "_-.".contains(c))
+            .collect();
+        let text = text.split_whitespace().collect::<Vec<_>>().join("-");
+        let text = text.to_lowercase();
+        let text = text.trim_start_matches(|c: char| !c.is_alphabetic());
+        let text = if text.is_empty() { "section" } else { text };
+        if repetition == 0 {
+            text.to_string()
+        } else {
+            format!("{text}-{repetition}")
+        }
+    }
 }
 
 struct Headers(HashMap<String, usize>);
@@ -432,20 +2196,152 @@ impl Headers {
     }
 }
 
+lazy_static! {
+    static ref BARE_URL: Regex = Regex::new(r"https?://[^\s<>]+").unwrap();
+}
+
 pub struct MdLinkParser<'a> {
     buffer: &'a str,
     parser: OffsetIter<'a>,
+    offset: usize,
     linenum: usize,
     oldoffs: usize,
+    bare_urls: bool,
+    skip_text: usize,
+    text: Option<Range<usize>>,
+    pending: VecDeque<MdLink<'a>>,
+    current: Option<MdLink<'a>>,
+}
+
+/// A link extracted by `MdLinkParser`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MdLink<'a> {
+    pub line: usize,
+    /// The column of the first character of the link, counted in characters.
+    pub col: usize,
+    pub url: CowStr<'a>,
+    /// The byte range of the whole link within the buffer.
+    pub span: Range<usize>,
+    /// The display text of the link, with any markup removed.
+    pub text: String,
+    pub title: Option<String>,
 }
 
 impl<'a> MdLinkParser<'a> {
     pub fn new(buffer: &'a str) -> Self {
+        let (front_matter, buffer) = split_front_matter(buffer);
         MdLinkParser {
             parser: Parser::new(buffer).into_offset_iter(),
             buffer,
-            linenum: 1,
+            offset: front_matter.len(),
+            linenum: 1 + count(front_matter.as_bytes(), b'\n'),
             oldoffs: 0,
+            bare_urls: false,
+            skip_text: 0,
+            text: None,
+            pending: VecDeque::new(),
+            current: None,
+        }
+    }
+
+    /// Also extracts bare URLs from plain text.
+    pub fn bare_urls(mut self, bare_urls: bool) -> Self {
+        self.bare_urls = bare_urls;
+        self
+    }
+
+    fn line_at(&mut self, offset: usize) -> usize {
+        if offset > self.oldoffs {
+            self.linenum += count(&self.buffer.as_bytes()[self.oldoffs..offset], b'\n');
+            self.oldoffs = offset;
+        }
+        self.linenum
+    }
+
+    fn make_link(&mut self, range: Range<usize>, url: CowStr<'a>) -> MdLink<'a> {
+        let line = self.line_at(range.start);
+        let col = col_at(self.buffer, range.start);
+        MdLink {
+            line,
+            col,
+            url,
+            span: self.offset + range.start..self.offset + range.end,
+            text: String::new(),
+            title: None,
+        }
+    }
+
+    fn scan_text(&mut self) {
+        if let Some(range) = self.text.take() {
+            let buffer = self.buffer;
+            for m in BARE_URL.find_iter(&buffer[range.clone()]) {
+                let url = trim_bare_url(m.as_str());
+                let start = range.start + m.start();
+                let mut link = self.make_link(start..start + url.len(), CowStr::Borrowed(url));
+                link.text = url.to_string();
+                self.pending.push_back(link);
+            }
+        }
+    }
+
+    /// Like `next` but also returns the column of the link.
+    pub fn next_link(&mut self) -> Option<MdLink<'a>> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                return Some(item);
+            }
+            let (event, range) = match self.parser.next() {
+                Some(next) => next,
+                None => {
+                    self.scan_text();
+                    return self.pending.pop_front();
+                }
+            };
+            if let Some(ref mut link) = self.current {
+                match event {
+                    Event::Text(ref text) | Event::Code(ref text) => link.text.push_str(text),
+                    Event::SoftBreak | Event::HardBreak => link.text.push(' '),
+                    _ => (),
+                }
+            }
+            if let Event::Text(_) = event {
+                if self.bare_urls && self.skip_text == 0 {
+                    match self.text {
+                        Some(ref mut text) if text.end == range.start => text.end = range.end,
+                        _ => {
+                            self.scan_text();
+                            self.text = Some(range);
+                        }
+                    }
+                }
+                continue;
+            }
+            self.scan_text();
+            match event {
+                Event::Start(pulldown_cmark::Tag::Link(_, url, title)) => {
+                    self.skip_text += 1;
+                    let mut link = self.make_link(range, url);
+                    if !title.is_empty() {
+                        link.title = Some(title.to_string());
+                    }
+                    self.current = Some(link);
+                }
+                Event::Start(pulldown_cmark::Tag::Image(..))
+                | Event::Start(pulldown_cmark::Tag::CodeBlock(_)) => {
+                    self.skip_text += 1;
+                }
+                Event::End(pulldown_cmark::Tag::Link(..)) => {
+                    self.skip_text -= 1;
+                    if let Some(link) = self.current.take() {
+                        self.pending.push_back(link);
+                    }
+                }
+                Event::End(pulldown_cmark::Tag::Image(..))
+                | Event::End(pulldown_cmark::Tag::CodeBlock(_)) => {
+                    self.skip_text -= 1;
+                }
+                _ => (),
+            }
         }
     }
 }
@@ -453,44 +2349,181 @@ impl<'a> MdLinkParser<'a> {
 impl<'a> Iterator for MdLinkParser<'a> {
     type Item = (usize, CowStr<'a>);
     fn next(&mut self) -> Option<Self::Item> {
-        for (event, range) in self.parser.by_ref() {
-            let offset = range.end;
-            if let Event::Start(pulldown_cmark::Tag::Link(_, url, _)) = event {
-                self.linenum += count(&self.buffer.as_bytes()[self.oldoffs..offset], b'\n');
-                self.oldoffs = offset;
-                return Some((self.linenum, url));
-            }
+        self.next_link().map(|link| (link.line, link.url))
+    }
+}
+
+/// Returns the one-based column of `offset` within its line, counted in characters.
+fn col_at(buffer: &str, offset: usize) -> usize {
+    let line_start = buffer[..offset].rfind('\n').map_or(0, |pos| pos + 1);
+    buffer[line_start..offset].chars().count() + 1
+}
+
+fn trim_bare_url(mut url: &str) -> &str {
+    loop {
+        let trimmed =
+            url.trim_end_matches(&['?', '!', '.', ',', ':', '*', '_', '~', '\'', '"'][..]);
+        let trimmed = if trimmed.ends_with(')')
+            && trimmed.matches('(').count() < trimmed.matches(')').count()
+        {
+            &trimmed[..trimmed.len() - 1]
+        } else {
+            trimmed
+        };
+        if trimmed.len() == url.len() {
+            return url;
         }
-        None
+        url = trimmed;
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Record {
     pub doc_path: PathBuf,
     pub doc_line: usize,
+    pub doc_col: Option<usize>,
     pub link: String,
+    pub text: Option<String>,
+    pub title: Option<String>,
+    /// The byte range of the whole link within the document, if known.
+    pub span: Option<Range<usize>>,
 }
 
 impl Record {
+    /// Resolves the link, joining root-absolute paths onto `base_url` if given.
+    ///
+    /// The path of `base_url` is expected to end with a slash.
     pub fn to_link<T: AsRef<Path>>(
         &self,
         base_path: &Option<T>,
-    ) -> result::Result<(Link, Option<String>), url::ParseError> {
+        base_url: Option<&Url>,
+    ) -> Result<(Link, Option<String>)> {
+        // Url::parse would silently strip surrounding whitespace, and Link::path
+        // would look up a wrong file
+        let trimmed = self.link.trim();
+        if trimmed != self.link || trimmed.contains(&['\n', '\r', '\t'][..]) {
+            return Err(Tag::MalformedLink
+                .as_error()
+                .context("raw", format!("{:?}", self.link))
+                .context("trimmed", trimmed.replace(&['\n', '\r', '\t'][..], "")));
+        }
         match Url::parse(&self.link) {
+            Ok(url) if url.scheme() == "file" => file_link(url),
             Ok(url) => Ok(Link::from_url(url)),
+            Err(url::ParseError::RelativeUrlWithoutBase)
+                if base_url.is_some()
+                    && self.link.starts_with('/')
+                    && !self.link.starts_with("//") =>
+            {
+                let url = base_url.unwrap().join(&self.link[1..])?;
+                Ok(Link::from_url(url))
+            }
             Err(url::ParseError::RelativeUrlWithoutBase) => Link::path(
                 &self.link,
                 &fs::canonicalize(&self.doc_path).unwrap(),
                 base_path,
             ),
-            Err(err) => Err(err),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Like `to_link`, but with several document roots tried in order.
+    ///
+    /// Absolute local links are joined to the first root under which the linked
+    /// document exists, falling back to the first root. Other links are resolved
+    /// within the first root containing the document.
+    pub fn to_link_with_roots<T: AsRef<Path>>(
+        &self,
+        roots: &[T],
+        base_url: Option<&Url>,
+    ) -> Result<(Link, Option<String>)> {
+        let path = self.link.split('#').next().unwrap_or_default();
+        if !Path::new(path).is_absolute() {
+            let doc_path = fs::canonicalize(&self.doc_path).ok();
+            let root = roots
+                .iter()
+                .find(|root| {
+                    doc_path
+                        .as_ref()
+                        .is_some_and(|doc_path| doc_path.starts_with(root))
+                })
+                .or_else(|| roots.first());
+            return self.to_link(&root, base_url);
+        }
+        let mut first = None;
+        for root in roots {
+            let link = self.to_link(&Some(root), base_url);
+            if matches!(link, Ok((Link::Path(ref path), _)) if path.exists()) {
+                return link;
+            }
+            first.get_or_insert(link);
+        }
+        first.unwrap_or_else(|| self.to_link(&None::<T>, base_url))
+    }
+
+    /// Returns whether a resolved link points back at the document the record
+    /// was found in.
+    ///
+    /// Both `#heading` and `current.md#heading` count, and since both resolve to
+    /// the same link they share one parse of the document.
+    pub fn is_same_doc(&self, link: &Link) -> bool {
+        if self.link.starts_with('#') {
+            return true;
+        }
+        match link {
+            Link::Path(path) => fs::canonicalize(&self.doc_path).is_ok_and(|doc| &doc == path),
+            Link::Url(_) => false,
+        }
+    }
+}
+
+/// Converts a `file:` URL into a local path link.
+fn file_link(url: Url) -> Result<(Link, Option<String>)> {
+    let fragment = url.fragment().map(std::string::ToString::to_string);
+    match url.to_file_path() {
+        Ok(path) => Ok((Link::Path(link::normalize(&path)), fragment)),
+        Err(()) => Err(Tag::Protocol
+            .as_error()
+            .context("host", url.host_str().unwrap_or_default().to_string())),
+    }
+}
+
+pub struct RootMarker {
+    name: PathBuf,
+    cache: HashMap<PathBuf, Option<PathBuf>>,
+}
+
+impl RootMarker {
+    pub fn new<P: AsRef<Path>>(name: P) -> Self {
+        RootMarker {
+            name: name.as_ref().into(),
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Finds the nearest ancestor of `dir` (including itself) containing the marker file.
+    pub fn find(&mut self, dir: &Path) -> Option<PathBuf> {
+        if let Some(root) = self.cache.get(dir) {
+            return root.clone();
         }
+        let root = if dir.join(&self.name).exists() {
+            Some(dir.to_path_buf())
+        } else {
+            dir.parent().and_then(|parent| self.find(parent))
+        };
+        self.cache.insert(dir.to_path_buf(), root.clone());
+        root
+    }
+
+    pub fn find_for_doc(&mut self, doc_path: &Path) -> Option<PathBuf> {
+        let doc_path = fs::canonicalize(doc_path).ok()?;
+        self.find(doc_path.parent()?)
     }
 }
 
 lazy_static! {
-    static ref RECORD_REGEX: Regex = Regex::new(r"^(.*):(\d+): [^ ]* ([^ ]*)$").unwrap();
+    static ref RECORD_REGEX: Regex =
+        Regex::new(r"^(.*?):(\d+):(?:(\d+):)? [^ ]* ([^ ]*)$").unwrap();
 }
 
 impl FromStr for Record {
@@ -500,29 +2533,674 @@ impl FromStr for Record {
         Ok(Record {
             doc_path: cap.get(1).unwrap().as_str().into(),
             doc_line: cap.get(2).unwrap().as_str().parse().unwrap(),
-            link: cap.get(3).unwrap().as_str().to_string(),
+            doc_col: cap.get(3).map(|col| col.as_str().parse().unwrap()),
+            link: cap.get(4).unwrap().as_str().to_string(),
+            text: None,
+            title: None,
+            span: None,
         })
     }
 }
 
-pub fn read_md(path: &str) -> result::Result<Box<dyn Iterator<Item = Record>>, io::Error> {
-    let mut buffer = String::new();
-    slurp(&path, &mut buffer)?;
-    let parser = MdLinkParser::new(buffer.as_str()).map(|(lineno, url)| Record {
-        doc_path: path.into(),
-        doc_line: lineno,
-        link: url.as_ref().to_string(),
-    });
-    Ok(Box::new(parser.collect::<Vec<_>>().into_iter()))
+/// The layout of records read from stdin.
+#[derive(Clone, Debug)]
+pub enum RecordFormat {
+    /// linky's own `path:line:col: TAG link` output format.
+    Linky,
+    /// Tab-separated `path`, `line` and `link` fields.
+    Tsv,
+    /// A regex with the named groups `path`, `line` and `link`, and optionally `col`.
+    Regex(Regex),
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn extract_links() {
-        let buffer = include_str!("../example_site/path/to/example.md");
+impl RecordFormat {
+    /// Parses a record from a line of input.
+    pub fn parse(&self, line: &str) -> result::Result<Record, String> {
+        let record = |path: &str, lineno: &str, col: Option<&str>, link: &str| {
+            Ok(Record {
+                doc_path: path.into(),
+                doc_line: lineno
+                    .parse()
+                    .map_err(|_| format!("invalid line number {lineno}"))?,
+                doc_col: col
+                    .map(|col| col.parse().map_err(|_| format!("invalid column {col}")))
+                    .transpose()?,
+                link: link.to_string(),
+                text: None,
+                title: None,
+                span: None,
+            })
+        };
+        match self {
+            RecordFormat::Linky => Record::from_str(line).map_err(str::to_string),
+            RecordFormat::Tsv => match line.split('\t').collect::<Vec<_>>()[..] {
+                [path, lineno, link] => record(path, lineno, None, link),
+                _ => Err("expected three tab-separated fields".to_string()),
+            },
+            RecordFormat::Regex(regex) => {
+                let cap = regex
+                    .captures(line)
+                    .ok_or_else(|| "no match for record format".to_string())?;
+                let group = |name| cap.name(name).map(|m| m.as_str());
+                record(
+                    group("path").unwrap_or_default(),
+                    group("line").unwrap_or_default(),
+                    group("col"),
+                    group("link").unwrap_or_default(),
+                )
+            }
+        }
+    }
+}
+
+impl FromStr for RecordFormat {
+    type Err = String;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "linky" => Ok(RecordFormat::Linky),
+            "tsv" => Ok(RecordFormat::Tsv),
+            _ => {
+                let pattern = s
+                    .strip_prefix("regex:")
+                    .ok_or_else(|| "expected linky, tsv or regex:PATTERN".to_string())?;
+                let regex = Regex::new(pattern).map_err(|err| err.to_string())?;
+                for name in ["path", "line", "link"] {
+                    if !regex.capture_names().any(|group| group == Some(name)) {
+                        return Err(format!("missing named group {name}"));
+                    }
+                }
+                Ok(RecordFormat::Regex(regex))
+            }
+        }
+    }
+}
+
+/// Reads a manifest of published files, one path per line relative to the manifest.
+pub fn read_manifest<P: AsRef<Path>>(path: P) -> io::Result<HashSet<PathBuf>> {
+    let path = fs::canonicalize(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("/"));
+    let mut buffer = String::new();
+    slurp(&path, &mut buffer)?;
+    Ok(buffer
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| link::normalize(&dir.join(line)))
+        .collect())
+}
+
+pub fn is_html<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext == "html" || ext == "htm")
+}
+
+/// Whether an error means that the linked document no longer exists.
+fn is_dead_link(url: &Url, err: &Error) -> bool {
+    match err.tag {
+        Tag::HttpStatus(status) => {
+            status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::GONE
+        }
+        Tag::NoDocument | Tag::DnsError => true,
+        Tag::HttpError => url.socket_addrs(|| None).is_err(),
+        _ => false,
+    }
+}
+
+pub fn is_asciidoc<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext == "adoc" || ext == "asciidoc")
+}
+
+pub fn read_html(path: &str) -> result::Result<Box<dyn Iterator<Item = Record>>, io::Error> {
+    let mut buffer = String::new();
+    slurp(&path, &mut buffer)?;
+    let records: Vec<_> = html_links(&buffer)
+        .into_iter()
+        .map(|(lineno, col, url)| Record {
+            doc_path: path.into(),
+            doc_line: lineno,
+            doc_col: Some(col),
+            link: url,
+            text: None,
+            title: None,
+            span: None,
+        })
+        .collect();
+    Ok(Box::new(records.into_iter()))
+}
+
+pub fn is_notebook<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().extension().is_some_and(|ext| ext == "ipynb")
+}
+
+/// Reads the links of the Markdown cells of a Jupyter notebook.
+///
+/// The cells are joined by blank lines, and line numbers count the lines of the
+/// joined cells, so they don't change when code cells or outputs do.
+pub fn read_ipynb(
+    path: &str,
+    bare_urls: bool,
+) -> result::Result<Box<dyn Iterator<Item = Record>>, io::Error> {
+    let mut buffer = String::new();
+    slurp(&path, &mut buffer)?;
+    let markdown = notebook_markdown(&buffer)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    let mut parser = MdLinkParser::new(&markdown).bare_urls(bare_urls);
+    // Byte ranges within the joined cells would be meaningless in the notebook
+    let records: Vec<_> = md_records(path, &mut parser)
+        .into_iter()
+        .map(|record| Record {
+            span: None,
+            ..record
+        })
+        .collect();
+    Ok(Box::new(records.into_iter()))
+}
+
+/// Joins the sources of the Markdown cells of a Jupyter notebook.
+fn notebook_markdown(buffer: &str) -> serde_json::Result<String> {
+    let notebook: serde_json::Value = serde_json::from_str(buffer)?;
+    let cells = notebook["cells"].as_array().map_or(&[][..], Vec::as_slice);
+    let mut markdown = String::new();
+    for cell in cells.iter().filter(|cell| cell["cell_type"] == "markdown") {
+        match &cell["source"] {
+            serde_json::Value::String(source) => markdown.push_str(source),
+            serde_json::Value::Array(lines) => {
+                lines
+                    .iter()
+                    .filter_map(serde_json::Value::as_str)
+                    .for_each(|line| markdown.push_str(line));
+            }
+            _ => (),
+        }
+        if !markdown.ends_with('\n') {
+            markdown.push('\n');
+        }
+        markdown.push('\n');
+    }
+    Ok(markdown)
+}
+
+pub fn is_mdx<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().extension().is_some_and(|ext| ext == "mdx")
+}
+
+/// Reads the links of an MDX document.
+///
+/// JSX elements, JavaScript expressions and `import`/`export` statements are
+/// blanked out before the Markdown is parsed, so that lines, columns and byte
+/// ranges still refer to the original file. The `href` and `src` attributes of
+/// JSX elements are extracted as well, when their values are string literals.
+pub fn read_mdx(
+    path: &str,
+    bare_urls: bool,
+) -> result::Result<Box<dyn Iterator<Item = Record>>, io::Error> {
+    let mut buffer = String::new();
+    slurp(&path, &mut buffer)?;
+    let (markdown, jsx_links) = mdx_markdown(&buffer);
+    let mut parser = MdLinkParser::new(&markdown).bare_urls(bare_urls);
+    let mut records = md_records(path, &mut parser);
+    records.extend(jsx_links.into_iter().map(|(lineno, col, url)| Record {
+        doc_path: path.into(),
+        doc_line: lineno,
+        doc_col: Some(col),
+        link: url,
+        text: None,
+        title: None,
+        span: None,
+    }));
+    records.sort_by_key(|record| (record.doc_line, record.doc_col));
+    Ok(Box::new(records.into_iter()))
+}
+
+lazy_static! {
+    static ref MDX_STATEMENT: Regex = Regex::new(r"^(?:import|export)\s").unwrap();
+    static ref JSX_TAG: Regex = Regex::new(r"^</?(?:[A-Za-z][\w.:-]*[\s/>]|>)").unwrap();
+    static ref JSX_LINK: Regex = Regex::new(
+        r#"\s(?:href|src)=(?:"([^"]*)"|'([^']*)'|\{\s*(?:"([^"]*)"|'([^']*)'|`([^`$]*)`)\s*\})"#
+    )
+    .unwrap();
+}
+
+/// Blanks out the MDX syntax of a buffer, leaving its Markdown at the same byte offsets.
+///
+/// Also returns the line, column and URL of each link found in a JSX element.
+fn mdx_markdown(buffer: &str) -> (String, Vec<(usize, usize, String)>) {
+    let mut blank = vec![false; buffer.len()];
+    let mut links = vec![];
+    let mut fence = None;
+    let mut offset = 0;
+    let mut statement = false;
+    let mut resume = 0;
+    for line in buffer.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        if resume >= offset {
+            continue;
+        }
+        if resume <= start {
+            if let Some(captures) = CODE_FENCE.captures(line) {
+                let marker = captures.get(1).unwrap().as_str();
+                match fence {
+                    None => fence = Some(marker),
+                    Some(open) if open == marker => fence = None,
+                    Some(_) => (),
+                }
+                continue;
+            }
+            if fence.is_some() {
+                continue;
+            }
+            // Statements continue until the next blank line
+            statement = (statement || MDX_STATEMENT.is_match(line)) && !line.trim().is_empty();
+            if statement {
+                blank[start..offset].iter_mut().for_each(|b| *b = true);
+                continue;
+            }
+        }
+        let mut pos = start.max(resume);
+        while pos < offset {
+            let end = match buffer.as_bytes()[pos] {
+                b'`' => {
+                    let ticks = buffer[pos..offset].len()
+                        - buffer[pos..offset].trim_start_matches('`').len();
+                    let marker = &buffer[pos..pos + ticks];
+                    pos += ticks;
+                    if let Some(close) = buffer[pos..offset].find(marker) {
+                        pos += close + ticks;
+                    }
+                    continue;
+                }
+                b'<' if JSX_TAG.is_match(&buffer[pos..]) => jsx_end(buffer, pos),
+                b'{' => jsx_end(buffer, pos),
+                _ => None,
+            };
+            // Anything unterminated is left to the Markdown parser
+            let end = match end {
+                Some(end) => end,
+                None => {
+                    pos += 1;
+                    continue;
+                }
+            };
+            if buffer.as_bytes()[pos] == b'<' {
+                for captures in JSX_LINK.captures_iter(&buffer[pos..end]) {
+                    let url = captures.iter().skip(1).flatten().next().unwrap();
+                    links.push((
+                        1 + count(&buffer.as_bytes()[..pos], b'\n'),
+                        col_at(buffer, pos),
+                        url.as_str().to_string(),
+                    ));
+                }
+            }
+            blank[pos..end].iter_mut().for_each(|b| *b = true);
+            // Elements may span lines, so scanning resumes after their ends
+            pos = end;
+            resume = end;
+        }
+    }
+    let markdown = buffer
+        .char_indices()
+        .map(|(pos, c)| {
+            if blank[pos] && c != '\n' {
+                " ".repeat(c.len_utf8())
+            } else {
+                c.to_string()
+            }
+        })
+        .collect();
+    (markdown, links)
+}
+
+/// Finds the end of the JSX tag or expression starting at `start`, skipping
+/// over quoted strings and nested braces.
+///
+/// Returns `None` if it isn't terminated before the end of its block.
+fn jsx_end(buffer: &str, start: usize) -> Option<usize> {
+    let bytes = buffer.as_bytes();
+    let is_tag = bytes[start] == b'<';
+    let mut braces = 0;
+    let mut quote = None;
+    for (pos, &b) in bytes.iter().enumerate().skip(start) {
+        if b == b'\n'
+            && buffer[pos + 1..]
+                .lines()
+                .next()
+                .is_none_or(|line| line.trim().is_empty())
+        {
+            return None;
+        }
+        match quote {
+            Some(q) if b == q => quote = None,
+            Some(_) => (),
+            None if is_tag && braces == 0 && b == b'>' => return Some(pos + 1),
+            None if b == b'"' || b == b'\'' || b == b'`' => quote = Some(b),
+            None if b == b'{' => braces += 1,
+            None if b == b'}' => {
+                braces -= 1;
+                if !is_tag && braces == 0 {
+                    return Some(pos + 1);
+                }
+            }
+            None => (),
+        }
+    }
+    None
+}
+
+pub fn read_text(path: &str) -> result::Result<Box<dyn Iterator<Item = Record>>, io::Error> {
+    let mut buffer = String::new();
+    slurp(&path, &mut buffer)?;
+    let records: Vec<_> = text_links(&buffer)
+        .into_iter()
+        .map(|(lineno, col, span)| Record {
+            doc_path: path.into(),
+            doc_line: lineno,
+            doc_col: Some(col),
+            link: buffer[span.clone()].to_string(),
+            text: None,
+            title: None,
+            span: Some(span),
+        })
+        .collect();
+    Ok(Box::new(records.into_iter()))
+}
+
+/// Scans a plain text buffer for `http:` and `https:` URLs.
+fn text_links(buffer: &str) -> Vec<(usize, usize, Range<usize>)> {
+    let mut links = vec![];
+    let mut linenum = 1;
+    let mut oldoffs = 0;
+    for m in BARE_URL.find_iter(buffer) {
+        let url = trim_bare_url(m.as_str());
+        linenum += count(&buffer.as_bytes()[oldoffs..m.start()], b'\n');
+        oldoffs = m.start();
+        links.push((
+            linenum,
+            col_at(buffer, m.start()),
+            m.start()..m.start() + url.len(),
+        ));
+    }
+    links
+}
+
+fn html_links(buffer: &str) -> Vec<(usize, usize, String)> {
+    let mut links = vec![];
+    let mut linenum = 1;
+    let mut oldoffs = 0;
+    for (pos, tag) in htmlstream::tag_iter(buffer) {
+        if tag.state != htmlstream::HTMLTagState::Opening
+            && tag.state != htmlstream::HTMLTagState::SelfClosing
+        {
+            continue;
+        }
+        for (_, attr) in htmlstream::attr_iter(&tag.attributes) {
+            if attr.name == "href" || attr.name == "src" {
+                linenum += count(&buffer.as_bytes()[oldoffs..pos.start], b'\n');
+                oldoffs = pos.start;
+                links.push((
+                    linenum,
+                    col_at(buffer, pos.start),
+                    decode_html_entities(&attr.value),
+                ));
+            }
+        }
+    }
+    links
+}
+
+fn decode_html_entities(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// The parsed Markdown documents of a run by canonical path, so that links
+/// between them are checked without reading the linked documents again.
+pub struct AnchorIndex {
+    id_transform: &'static dyn ToId,
+    documents: sync::Mutex<HashMap<PathBuf, Document<'static>>>,
+}
+
+impl AnchorIndex {
+    /// Creates an empty index, with anchors generated by `id_transform`.
+    pub fn new(id_transform: &'static dyn ToId) -> Self {
+        AnchorIndex {
+            id_transform,
+            documents: sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Like `read_md`, but also adds the document to the index.
+    pub fn read_md(
+        &self,
+        path: &str,
+        bare_urls: bool,
+    ) -> result::Result<Box<dyn Iterator<Item = Record>>, io::Error> {
+        let mut buffer = String::new();
+        slurp(&path, &mut buffer)?;
+        let content_type = if is_asciidoc(path) {
+            &*ASCIIDOC_CONTENT_TYPE
+        } else {
+            &*MARKDOWN_CONTENT_TYPE
+        };
+        let document = Document::parse_with(
+            buffer.as_bytes(),
+            content_type,
+            self.id_transform,
+            encoding_rs::UTF_8,
+            KeepText::default(),
+        );
+        if let (Ok(document), Ok(canonical)) = (document, fs::canonicalize(path)) {
+            self.documents.lock().unwrap().insert(canonical, document);
+        }
+        let mut parser = MdLinkParser::new(buffer.as_str()).bare_urls(bare_urls);
+        Ok(Box::new(md_records(path, &mut parser).into_iter()))
+    }
+
+    fn get(&self, path: &Path) -> Option<Document<'static>> {
+        self.documents.lock().unwrap().get(path).cloned()
+    }
+}
+
+pub fn read_md(
+    path: &str,
+    bare_urls: bool,
+) -> result::Result<Box<dyn Iterator<Item = Record>>, io::Error> {
+    let mut buffer = String::new();
+    slurp(&path, &mut buffer)?;
+    let mut parser = MdLinkParser::new(buffer.as_str()).bare_urls(bare_urls);
+    Ok(Box::new(md_records(path, &mut parser).into_iter()))
+}
+
+/// Extracts the links of a Markdown document that is already in memory.
+///
+/// The `source_name` takes the place of the file path in the records.
+pub fn read_md_str(source_name: &str, buffer: &str) -> Vec<Record> {
+    md_records(source_name, &mut MdLinkParser::new(buffer))
+}
+
+fn md_records(doc_path: &str, parser: &mut MdLinkParser) -> Vec<Record> {
+    iter::from_fn(|| parser.next_link())
+        .map(|link| Record {
+            doc_path: doc_path.into(),
+            doc_line: link.line,
+            doc_col: Some(link.col),
+            link: link.url.as_ref().to_string(),
+            text: Some(link.text),
+            title: link.title,
+            span: Some(link.span),
+        })
+        .collect()
+}
+
+/// Reads the link reference definitions of a Markdown file that no link refers to.
+pub fn read_unused_defs(path: &str) -> result::Result<Box<dyn Iterator<Item = Record>>, io::Error> {
+    let mut buffer = String::new();
+    slurp(&path, &mut buffer)?;
+    let records: Vec<_> = unused_definitions(&buffer)
+        .into_iter()
+        .map(|definition| Record {
+            doc_path: path.into(),
+            doc_line: definition.line,
+            doc_col: Some(definition.col),
+            link: definition.url.as_ref().to_string(),
+            text: Some(definition.text),
+            title: None,
+            span: Some(definition.span),
+        })
+        .collect();
+    Ok(Box::new(records.into_iter()))
+}
+
+lazy_static! {
+    static ref LINK_DEFINITION: Regex =
+        Regex::new(r"^ {0,3}\[((?:[^\]\\]|\\.)+)\]:[ \t]*<?([^\s>]+)").unwrap();
+    static ref CODE_FENCE: Regex = Regex::new(r"^ {0,3}(```|~~~)").unwrap();
+}
+
+/// Splits a buffer starting with a bracketed text into the text and the rest.
+fn bracketed(buffer: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    let mut escaped = false;
+    for (pos, c) in buffer.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => depth += 1,
+            ']' if depth == 1 => return Some((&buffer[1..pos], &buffer[pos + 1..])),
+            ']' => depth -= 1,
+            _ if depth == 0 => return None,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Normalizes a link label the way CommonMark matches labels.
+fn normalize_label(label: &str) -> String {
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Returns the link reference definitions of a Markdown document that no link
+/// or image refers to, with their labels as link texts.
+///
+/// pulldown-cmark doesn't report definitions, so they are found by scanning the
+/// lines outside of fenced code blocks. Footnote definitions are not included.
+pub fn unused_definitions(buffer: &str) -> Vec<MdLink<'_>> {
+    let (front_matter, body) = split_front_matter(buffer);
+    let mut used = HashSet::new();
+    for (event, range) in Parser::new(body).into_offset_iter() {
+        let link_type = match event {
+            Event::Start(pulldown_cmark::Tag::Link(link_type, ..))
+            | Event::Start(pulldown_cmark::Tag::Image(link_type, ..)) => link_type,
+            _ => continue,
+        };
+        // The range only covers the opening bracket of reference links
+        let source = body[range.start..].trim_start_matches('!');
+        let label = match link_type {
+            LinkType::Reference => bracketed(source)
+                .and_then(|(_, rest)| bracketed(rest))
+                .map(|(label, _)| label),
+            LinkType::Collapsed | LinkType::Shortcut => bracketed(source).map(|(label, _)| label),
+            _ => None,
+        };
+        if let Some(label) = label {
+            used.insert(normalize_label(label));
+        }
+    }
+
+    let mut definitions = vec![];
+    let mut fence = None;
+    let mut offset = front_matter.len();
+    let linenum = 1 + count(front_matter.as_bytes(), b'\n');
+    for (lineno, line) in body.split_inclusive('\n').enumerate() {
+        let start = offset;
+        offset += line.len();
+        if let Some(captures) = CODE_FENCE.captures(line) {
+            let marker = captures.get(1).unwrap().as_str();
+            match fence {
+                None => fence = Some(marker),
+                Some(open) if open == marker => fence = None,
+                Some(_) => (),
+            }
+            continue;
+        }
+        if fence.is_some() {
+            continue;
+        }
+        let captures = match LINK_DEFINITION.captures(line) {
+            Some(captures) => captures,
+            None => continue,
+        };
+        let label = captures.get(1).unwrap().as_str();
+        if label.starts_with('^') || used.contains(&normalize_label(label)) {
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+        definitions.push(MdLink {
+            line: linenum + lineno,
+            col: indent + 1,
+            url: CowStr::Borrowed(captures.get(2).unwrap().as_str()),
+            span: start + indent..start + line.trim_end().len(),
+            text: label.to_string(),
+            title: None,
+        });
+    }
+    definitions
+}
+
+/// Rewrites the links of a Markdown document for which `replace` returns a replacement.
+///
+/// A link is only rewritten if its URL occurs exactly once within the link, so
+/// that escaped or otherwise ambiguous URLs are left untouched. Returns the
+/// rewritten document and the number of rewritten links.
+pub fn rewrite_md_links<F>(buffer: &str, bare_urls: bool, mut replace: F) -> (String, usize)
+where
+    F: FnMut(&MdLink) -> Option<String>,
+{
+    let mut parser = MdLinkParser::new(buffer).bare_urls(bare_urls);
+    let mut rewritten = String::with_capacity(buffer.len());
+    let mut copied = 0;
+    let mut count = 0;
+    while let Some(link) = parser.next_link() {
+        if link.span.start < copied || link.url.is_empty() {
+            continue;
+        }
+        let replacement = match replace(&link) {
+            Some(replacement) => replacement,
+            None => continue,
+        };
+        let source = &buffer[link.span.clone()];
+        let mut matches = source.match_indices(link.url.as_ref());
+        if let (Some((pos, _)), None) = (matches.next(), matches.next()) {
+            let start = link.span.start + pos;
+            rewritten.push_str(&buffer[copied..start]);
+            rewritten.push_str(&replacement);
+            copied = start + link.url.len();
+            count += 1;
+        }
+    }
+    rewritten.push_str(&buffer[copied..]);
+    (rewritten, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_links() {
+        let buffer = include_str!("../example_site/path/to/example.md");
         let mut parser = MdLinkParser::new(buffer);
         assert_eq!(
             parser.next(),
@@ -545,72 +3223,1776 @@ mod tests {
     }
 
     #[test]
-    fn fragment() {
+    fn extract_autolinks() {
+        let buffer = include_str!("../example_site/path/to/autolink.md");
+        let mut parser = MdLinkParser::new(buffer);
         assert_eq!(
-            FragResolver::new()
-                .fragment(&Document::from(&["abc"]), "abc")
-                .map_err(|e| e.tag),
-            Ok(())
+            parser.next(),
+            Some((2, "https://github.com/mattias-p/linky".into()))
         );
+        assert_eq!(parser.next(), Some((5, "other.md".into())));
+        assert_eq!(parser.next(), None);
+
+        let mut parser = MdLinkParser::new(buffer).bare_urls(true);
         assert_eq!(
-            FragResolver::new()
-                .fragment(&Document::new(), "")
-                .map_err(|e| e.tag),
-            Ok(())
+            parser.next(),
+            Some((2, "https://github.com/mattias-p/linky".into()))
         );
         assert_eq!(
-            FragResolver::new()
-                .fragment(&Document::empty(), "")
-                .map_err(|e| e.tag),
-            Err(Tag::NoFragment)
+            parser.next(),
+            Some((
+                3,
+                "https://github.com/mattias-p/linky/blob/master/README.md".into()
+            ))
         );
         assert_eq!(
-            FragResolver::from(&["prefix"])
-                .fragment(&Document::from(&["prefix"]), "")
-                .map_err(|e| e.tag),
-            Ok(())
+            parser.next(),
+            Some((
+                4,
+                "https://en.wikipedia.org/wiki/Linky_(disambiguation)".into()
+            ))
         );
+        assert_eq!(parser.next(), Some((5, "other.md".into())));
+        assert_eq!(parser.next(), None);
+    }
+
+    #[test]
+    fn byte_ranges() {
+        let path = "example_site/path/to/example.md";
+        let buffer = fs::read_to_string(path).unwrap();
+        let record = read_md(path, false).unwrap().next().unwrap();
         assert_eq!(
-            FragResolver::new()
-                .fragment(&Document::new(), "abc")
-                .map_err(|e| e.tag),
-            Err(Tag::NoFragment)
+            &buffer[record.span.unwrap()],
+            "[remote link without fragment, ok](https://github.com/mattias-p/linky/blob/master/example_site/path/to/other.md)"
         );
+    }
+
+    #[test]
+    fn anchor_index() {
+        let path = std::env::temp_dir().join("linky-anchor-index-test.md");
+        fs::write(&path, "# Indexed\n* [link](#indexed)\n").unwrap();
+        let index = sync::Arc::new(AnchorIndex::new(&GithubId));
+        let records: Vec<_> = index
+            .read_md(path.to_str().unwrap(), false)
+            .unwrap()
+            .collect();
+        assert_eq!(records.len(), 1);
+        // The indexed document is used even after the file changes
+        fs::write(&path, "# Changed\n").unwrap();
+        let client = Client::builder().build().anchor_index(Some(index));
+        let link = Link::Path(fs::canonicalize(&path).unwrap());
+        let document = client.fetch_link(false, &link).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(document.ids.contains("indexed"));
+        assert!(!document.ids.contains("changed"));
+        assert_eq!(document.lines, Some(2));
+    }
+
+    #[test]
+    fn notebook_links() {
+        let links: Vec<_> = read_ipynb("example_site/path/to/notebook.ipynb", false)
+            .unwrap()
+            .map(|record| (record.doc_line, record.doc_col, record.link, record.span))
+            .collect();
         assert_eq!(
-            FragResolver::new()
-                .fragment(&Document::from(&["abc-123"]), "123")
-                .map_err(|e| e.tag),
-            Err(Tag::NoFragment)
+            links,
+            vec![
+                (2, Some(3), "other.md#existing".to_string(), None),
+                (4, Some(3), "other.md#non-existing".to_string(), None),
+            ]
         );
+        assert!(read_ipynb("example_site/path/to/other.md", false).is_err());
+    }
+
+    #[test]
+    fn md_records_from_str() {
+        let records = read_md_str("buffer", "# Title\n\nSee [other](other.md#x \"Other\").\n");
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.doc_path, PathBuf::from("buffer"));
+        assert_eq!((record.doc_line, record.doc_col), (3, Some(5)));
+        assert_eq!(record.link, "other.md#x");
+        assert_eq!(record.text.as_deref(), Some("other"));
+        assert_eq!(record.title.as_deref(), Some("Other"));
         assert_eq!(
-            FragResolver::from(&["abc-"])
-                .fragment(&Document::from(&["abc-123"]), "123")
-                .map_err(|e| e.tag),
-            Err(Tag::Prefixed)
+            read_md("example_site/path/to/other.md", false)
+                .unwrap()
+                .map(|record| record.link)
+                .collect::<Vec<_>>(),
+            read_md_str(
+                "_",
+                &fs::read_to_string("example_site/path/to/other.md").unwrap()
+            )
+            .into_iter()
+            .map(|record| record.link)
+            .collect::<Vec<_>>()
         );
     }
 
     #[test]
-    fn find_prefix() {
+    fn unused_link_definitions() {
+        let buffer = "---\ntitle: x\n---\n[full][One] [two][] [Three] ![four]\n\n\
+                      [one]: http://one/\n[two]: http://two/\n[three]: http://three/\n\
+                      [four]: four.png\n  [Five  Six]: <http://five/> \"Title\"\n\
+                      [^7]: A footnote.\n\n```\n[eight]: http://eight/\n```\n";
+        let unused: Vec<_> = unused_definitions(buffer)
+            .into_iter()
+            .map(|link| (link.line, link.col, link.url.to_string(), link.text))
+            .collect();
         assert_eq!(
-            FragResolver::new().find_prefix("123", &Document::from(&["123"])),
-            Some("")
+            unused,
+            vec![(10, 3, "http://five/".to_string(), "Five  Six".to_string())]
         );
+        let link = &unused_definitions(buffer)[0];
         assert_eq!(
-            FragResolver::from(&["abc-", "def-"]).find_prefix("123", &Document::from(&["def-123"])),
-            Some("def-")
+            &buffer[link.span.clone()],
+            "[Five  Six]: <http://five/> \"Title\""
+        );
+    }
+
+    #[test]
+    fn extract_text_links() {
+        let buffer = "See https://example.com/a.\n// (docs: http://example.com/b?x=1)\nnone here\n";
+        let links: Vec<_> = text_links(buffer)
+            .into_iter()
+            .map(|(line, col, span)| (line, col, &buffer[span]))
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                (1, 5, "https://example.com/a"),
+                (2, 11, "http://example.com/b?x=1"),
+            ]
         );
+    }
+
+    #[test]
+    fn utf16_documents() {
+        let path = "example_site/path/to/utf16.md";
+        let record = read_md(path, false).unwrap().next().unwrap();
+        assert_eq!((record.doc_line, record.link.as_str()), (2, "other.md"));
+
+        let client = Client::builder().build();
+        let resolver = FragResolver::new();
+        let link = Link::Path(fs::canonicalize(path).unwrap());
+        let fragment = Some("utf-16-heading".to_string());
         assert_eq!(
-            FragResolver::from(&["abc-"]).find_prefix("123", &Document::from(&["def-123"])),
-            None
+            check_link(&client, &resolver, &link, &fragment, false, None).map_err(|e| e.tag),
+            Ok(())
         );
+
+        let buffer = b"\xEF\xBB\xBF# Heading\n";
+        let document = Document::parse(&buffer[..], &MARKDOWN_CONTENT_TYPE).unwrap();
+        assert!(document.ids.contains("heading"));
     }
 
     #[test]
-    fn decoding() {
-        let latin1 = b"\xC4ntligen stod pr\xE4sten i predikstolen.".to_vec();
+    fn short_files() {
+        let path = std::env::temp_dir().join("linky-short-file-test.md");
+        for content in ["a", "abc", "---"] {
+            fs::write(&path, content).unwrap();
+            let mut buffer = String::new();
+            slurp(&path, &mut buffer).unwrap();
+            assert_eq!(buffer, content);
+            assert_eq!(read_md(path.to_str().unwrap(), false).unwrap().count(), 0);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn columns() {
+        let mut parser = MdLinkParser::new("# Åäö\n[a](a.md) and [b](b.md)\n").bare_urls(true);
+        assert_eq!(
+            parser
+                .next_link()
+                .map(|link| (link.line, link.col, link.url)),
+            Some((2, 1, "a.md".into()))
+        );
+        assert_eq!(
+            parser
+                .next_link()
+                .map(|link| (link.line, link.col, link.url)),
+            Some((2, 15, "b.md".into()))
+        );
+        assert_eq!(parser.next_link(), None);
+        assert_eq!(col_at("åäö x", "åäö ".len()), 5);
+    }
+
+    #[test]
+    fn link_text() {
+        let buffer = "See [the *other* `doc`](other.md \"Other\") and http://example.com/\n";
+        let mut parser = MdLinkParser::new(buffer).bare_urls(true);
+        let link = parser.next_link().unwrap();
+        assert_eq!(link.text, "the other doc");
+        assert_eq!(link.title.as_deref(), Some("Other"));
+        let link = parser.next_link().unwrap();
+        assert_eq!(link.text, "http://example.com/");
+        assert_eq!(link.title, None);
+        assert_eq!(parser.next_link(), None);
+    }
+
+    #[test]
+    fn rewrite_links() {
+        let buffer = "---\ntitle: x\n---\n[a](http://a.example/#x) [b](<http://a.example/#x>)\n\
+                      [c](http://c.example/ \"http://c.example/\") http://a.example/#x\n";
+        let (rewritten, count) = rewrite_md_links(buffer, true, |link| {
+            link.url
+                .strip_prefix("http://")
+                .map(|rest| format!("https://{rest}"))
+        });
+        assert_eq!(count, 3);
+        assert_eq!(
+            rewritten,
+            "---\ntitle: x\n---\n[a](https://a.example/#x) [b](<https://a.example/#x>)\n\
+             [c](http://c.example/ \"http://c.example/\") https://a.example/#x\n"
+        );
+    }
+
+    #[test]
+    fn front_matter() {
+        let buffer = "---\ntitle: [Title](title.md)\n---\n# Heading\n[link](other.md)\n";
+        let mut parser = MdLinkParser::new(buffer);
+        assert_eq!(parser.next(), Some((5, "other.md".into())));
+        assert_eq!(parser.next(), None);
+
+        let mut headers = Headers::new();
+        let ids: Vec<_> = MdAnchorParser::from_buffer(buffer, &GithubId, &mut headers).collect();
+        assert_eq!(ids, vec!["heading".to_string()]);
+
+        assert_eq!(
+            split_front_matter("+++\ntitle = \"Title\"\n+++\nbody"),
+            ("+++\ntitle = \"Title\"\n+++\n", "body")
+        );
+        assert_eq!(
+            split_front_matter("---\nno closing delimiter\n"),
+            ("", "---\nno closing delimiter\n")
+        );
+        assert_eq!(split_front_matter("---"), ("", "---"));
+        assert_eq!(split_front_matter("+++"), ("", "+++"));
+        assert!(read_md_str("x", "---").is_empty());
+        assert!(read_md_str("x", "+++").is_empty());
+    }
+
+    #[test]
+    fn extract_html_links() {
+        let buffer = include_str!("../example_site/path/to/page.html");
+        assert_eq!(
+            html_links(buffer),
+            vec![
+                (5, 4, "other.md".to_string()),
+                (6, 4, "page.html#heading".to_string()),
+                (7, 4, "page.html#non-existing".to_string()),
+                (
+                    8,
+                    4,
+                    "https://github.com/mattias-p/linky?tab=readme&lang=en".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn fragment() {
+        assert_eq!(
+            FragResolver::new()
+                .fragment(&Document::from(&["abc"]), "abc")
+                .map_err(|e| e.tag),
+            Ok(())
+        );
+        assert_eq!(
+            FragResolver::new()
+                .fragment(&Document::new(), "")
+                .map_err(|e| e.tag),
+            Ok(())
+        );
+        assert_eq!(
+            FragResolver::new()
+                .fragment(&Document::empty(), "")
+                .map_err(|e| e.tag),
+            Err(Tag::NoFragment)
+        );
+        assert_eq!(
+            FragResolver::from(&["prefix"])
+                .fragment(&Document::from(&["prefix"]), "")
+                .map_err(|e| e.tag),
+            Ok(())
+        );
+        assert_eq!(
+            FragResolver::new()
+                .fragment(&Document::new(), "abc")
+                .map_err(|e| e.tag),
+            Err(Tag::NoFragment)
+        );
+        assert_eq!(
+            FragResolver::new()
+                .fragment(&Document::from(&["abc-123"]), "123")
+                .map_err(|e| e.tag),
+            Err(Tag::NoFragment)
+        );
+        assert_eq!(
+            FragResolver::from(&["abc-"])
+                .fragment(&Document::from(&["abc-123"]), "123")
+                .map_err(|e| e.tag),
+            Err(Tag::Prefixed)
+        );
+    }
+
+    #[test]
+    fn text_fragments() {
+        let document = Document::parse(
+            "<p id=\"intro\">The <b>quick</b> brown\n fox &amp; the lazy dog</p>\
+             <script>var hidden;</script>"
+                .as_bytes(),
+            &mime::TEXT_HTML_UTF_8,
+        )
+        .map_err(sync::Arc::new);
+        let base = Link::Url(Url::parse("https://example.com/").unwrap());
+        let resolver = FragResolver::new();
+        let check = |fragment: &str| {
+            resolver
+                .document(&document, &base, &Some(fragment.to_string()))
+                .map_err(|err| err.tag)
+        };
+        assert_eq!(check(":~:text=quick%20brown"), Ok(()));
+        assert_eq!(check(":~:text=The%20Quick,lazy"), Ok(()));
+        assert_eq!(check(":~:text=the-,quick,-brown"), Ok(()));
+        assert_eq!(check(":~:text=fox%20%26%20the&text=dog"), Ok(()));
+        assert_eq!(check("intro:~:text=dog"), Ok(()));
+        assert_eq!(check("outro:~:text=dog"), Err(Tag::NoFragment));
+        assert_eq!(check(":~:text=lazy,quick"), Err(Tag::NoTextFragment));
+        assert_eq!(check(":~:text=a-,quick"), Err(Tag::NoTextFragment));
+        assert_eq!(check(":~:text=hidden"), Err(Tag::NoTextFragment));
+
+        let document = Document::parse(
+            "Un*believ*able\n\n# Heading\n".as_bytes(),
+            &MARKDOWN_CONTENT_TYPE,
+        )
+        .map_err(sync::Arc::new);
+        assert!(resolver
+            .document(
+                &document,
+                &base,
+                &Some(":~:text=unbelievable%20heading".to_string())
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn text_kept_only_when_checked() {
+        let parse = |client: Client| {
+            client
+                .parse("<p>Some <b>Text</b></p>".as_bytes(), &mime::TEXT_HTML_UTF_8)
+                .unwrap()
+        };
+        let document = parse(Client::builder().build());
+        assert_eq!((document.text, document.lowercase_text), (None, None));
+        let document = parse(Client::builder().build().text_fragments(true));
+        assert_eq!(document.text, None);
+        assert_eq!(document.lowercase_text.as_deref(), Some("some text"));
+        let regex = Regex::new("Text").unwrap();
+        let document = parse(Client::builder().build().expect_present(vec![regex]));
+        assert_eq!(document.text.as_deref(), Some("Some Text"));
+        assert_eq!(document.lowercase_text, None);
+    }
+
+    #[test]
+    fn find_prefix() {
+        assert_eq!(
+            FragResolver::new().find_prefix("123", &Document::from(&["123"])),
+            Some("")
+        );
+        assert_eq!(
+            FragResolver::from(&["abc-", "def-"]).find_prefix("123", &Document::from(&["def-123"])),
+            Some("def-")
+        );
+        assert_eq!(
+            FragResolver::from(&["abc-"]).find_prefix("123", &Document::from(&["def-123"])),
+            None
+        );
+    }
+
+    #[test]
+    fn github_compat() {
+        let document = Ok(Document::from(&["user-content-installation"]));
+        let fragment = Some("installation".to_string());
+        let check = |resolver: &FragResolver, url| {
+            resolver
+                .document(&document, &Link::Url(Url::parse(url).unwrap()), &fragment)
+                .map_err(|err| err.tag)
+        };
+        let resolver = FragResolver::from(&[]).github_compat(true);
+        assert_eq!(check(&resolver, "https://github.com/owner/repo"), Ok(()));
+        assert_eq!(
+            check(&resolver, "https://example.com/owner/repo"),
+            Err(Tag::NoFragment)
+        );
+        let resolver = FragResolver::from(&[]);
+        assert_eq!(
+            check(&resolver, "https://github.com/owner/repo"),
+            Err(Tag::NoFragment)
+        );
+    }
+
+    #[test]
+    fn fragment_soft_fail() {
+        let document = Ok(Document::new());
+        let fragment = Some("rendered".to_string());
+        let resolver = FragResolver::from(&[]).fragment_soft_fail(true);
+        let check = |base: &Link| {
+            resolver
+                .document(&document, base, &fragment)
+                .map_err(|err| err.tag)
+        };
+        let remote = Link::Url(Url::parse("https://example.com/app").unwrap());
+        assert_eq!(check(&remote), Err(Tag::NoFragmentSoft));
+        assert!(Tag::NoFragmentSoft.is_warning());
+        assert_eq!(check(&Link::Path("/doc.md".into())), Err(Tag::NoFragment));
+    }
+
+    #[test]
+    fn check_local_link() {
+        let client = Client::builder().build();
+        let resolver = FragResolver::new();
+        let path = fs::canonicalize("example_site/path/to/other.md").unwrap();
+        let link = Link::Path(path);
+        assert_eq!(
+            check_link(
+                &client,
+                &resolver,
+                &link,
+                &Some("existing".to_string()),
+                false,
+                None
+            )
+            .map_err(|e| e.tag),
+            Ok(())
+        );
+        let document = client.fetch_link(false, &link);
+        assert_eq!(
+            check_link(
+                &client,
+                &resolver,
+                &link,
+                &Some("non-existing".to_string()),
+                false,
+                Some(&document)
+            )
+            .map_err(|e| e.tag),
+            Err(Tag::NoFragment)
+        );
+    }
+
+    #[test]
+    fn index_files() {
+        let resolver = FragResolver::new();
+        let link = Link::Path(fs::canonicalize("example_site/path/to").unwrap());
+        let check = |client: &Client, fragment: &str| {
+            let fragment = Some(fragment.to_string()).filter(|fragment| !fragment.is_empty());
+            check_link(client, &resolver, &link, &fragment, false, None).map_err(|e| e.tag)
+        };
+        let client = Client::builder().build();
+        assert_eq!(check(&client, ""), Err(Tag::Directory));
+        let client = Client::builder()
+            .build()
+            .index_files(vec!["index.html".to_string(), "other.md".to_string()]);
+        assert_eq!(check(&client, ""), Ok(()));
+        assert_eq!(check(&client, "existing"), Ok(()));
+        assert_eq!(check(&client, "non-existing"), Err(Tag::NoFragment));
+    }
+
+    #[test]
+    fn base_href() {
+        let url = Url::parse("http://example.com/a/page.html").unwrap();
+        let html = br#"<html><head><base href="/b/"><base href="/c/"></head></html>"#;
+        let document = Document::parse(&html[..], &mime::TEXT_HTML_UTF_8).unwrap();
+        assert_eq!(document.base_href.as_deref(), Some("/b/"));
+        assert_eq!(document.base_url(&url).as_str(), "http://example.com/b/");
+        let document = Document::parse(&b"<html></html>"[..], &mime::TEXT_HTML_UTF_8).unwrap();
+        assert_eq!(document.base_url(&url), url);
+    }
+
+    #[test]
+    fn meta_refresh() {
+        let html = br#"<meta http-equiv="Refresh" content="0; URL='/new/'">"#;
+        let document = Document::parse(&html[..], &mime::TEXT_HTML_UTF_8).unwrap();
+        assert_eq!(document.refresh.as_deref(), Some("/new/"));
+        let html = br#"<meta http-equiv="refresh" content="30">"#;
+        let document = Document::parse(&html[..], &mime::TEXT_HTML_UTF_8).unwrap();
+        assert_eq!(document.refresh, None);
+    }
+
+    #[test]
+    fn uncheckable_fragment() {
+        let resolver = FragResolver::new();
+        let link = Link::Url(Url::parse("http://example.com/doc.pdf").unwrap());
+        let pdf: mime::Mime = "application/pdf".parse().unwrap();
+        let document = Document::parse(&b"%PDF-1.4"[..], &pdf).map_err(sync::Arc::new);
+        assert_eq!(
+            resolver
+                .document(&document, &link, &Some("page=2".to_string()))
+                .map_err(|e| e.tag),
+            Err(Tag::UncheckableFragment)
+        );
+        assert_eq!(
+            resolver
+                .document(&document, &link, &None)
+                .map_err(|e| e.tag),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn manifest() {
+        let path = std::env::temp_dir().join("linky-manifest-test.txt");
+        let other = fs::canonicalize("example_site/path/to/other.md").unwrap();
+        fs::write(&path, format!("# published\n{}\n", other.display())).unwrap();
+        let manifest = read_manifest(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let client = Client::builder()
+            .build()
+            .manifest(Some(sync::Arc::new(manifest)));
+        assert!(client.fetch_link(false, &Link::Path(other)).is_ok());
+        let example = fs::canonicalize("example_site/path/to/example.md").unwrap();
+        assert_eq!(
+            client
+                .fetch_link(false, &Link::Path(example))
+                .map(|_| ())
+                .map_err(|e| e.tag),
+            Err(Tag::NotPublished)
+        );
+    }
+
+    #[test]
+    fn anchors_from_str() {
+        struct UpperId;
+
+        impl ToId for UpperId {
+            fn to_id(&self, text: &str, repetition: usize) -> String {
+                format!("{}{}", text.to_uppercase(), repetition)
+            }
+        }
+
+        let anchors = |buffer, content_type: &str, id_transform: &dyn ToId| {
+            let mut anchors: Vec<_> =
+                Document::anchors_from_str(buffer, &content_type.parse().unwrap(), id_transform)
+                    .unwrap()
+                    .into_iter()
+                    .collect();
+            anchors.sort();
+            anchors
+        };
+        let markdown = "# 1. Setup\n# Usage\n# Usage\n";
+        assert_eq!(
+            anchors(markdown, "text/markdown", &GithubId),
+            vec!["1-setup", "usage", "usage-1"]
+        );
+        assert_eq!(
+            anchors(
+                markdown,
+                "text/markdown",
+                AnchorStyle::Pandoc.id_transform()
+            ),
+            vec!["setup", "usage", "usage-1"]
+        );
+        assert_eq!(
+            anchors(markdown, "text/markdown", &UpperId),
+            vec!["1. SETUP0", "USAGE0", "USAGE1"]
+        );
+        assert_eq!(
+            anchors(
+                r#"<h1 id="top">Top</h1><a name="end"></a>"#,
+                "text/html",
+                &GithubId
+            ),
+            vec!["end", "top"]
+        );
+        let footnotes = "# Notes\nSee[^1].\n\n[^1]: A footnote.\n";
+        assert_eq!(
+            anchors(footnotes, "text/markdown", &GithubId),
+            vec![
+                "fn-1",
+                "fnref-1",
+                "notes",
+                "user-content-fn-1",
+                "user-content-fnref-1"
+            ]
+        );
+        assert_eq!(
+            anchors(footnotes, "text/markdown", &UpperId),
+            vec!["NOTES0"]
+        );
+        assert_eq!(
+            anchors("{}", "application/json", &GithubId),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn line_anchors() {
+        let client = Client::builder().build();
+        let resolver = FragResolver::new();
+        let link = Link::Path(fs::canonicalize("example_site/path/to/other.md").unwrap());
+        let document = client.fetch_link(false, &link);
+        let lines = document.as_ref().unwrap().lines.unwrap();
+        let check = |fragment: String| {
+            resolver
+                .document(&document, &link, &Some(fragment))
+                .map_err(|e| e.tag)
+        };
+        assert_eq!(check("L1".to_string()), Ok(()));
+        assert_eq!(check(format!("L1-L{lines}")), Ok(()));
+        assert_eq!(check(format!("L{}", lines + 1)), Err(Tag::NoFragment));
+        assert_eq!(check("L2-L1".to_string()), Err(Tag::NoFragment));
+    }
+
+    #[test]
+    fn duplicate_anchors() {
+        let client = Client::builder().build().warn_duplicate_anchors(true);
+        let resolver = FragResolver::new();
+        let link = Link::Path(fs::canonicalize("example_site/path/to/other.md").unwrap());
+        let buffer = b"# Setup\n# Usage\n# Setup\n# Setup\n";
+        let document = Document::parse(&buffer[..], &MARKDOWN_CONTENT_TYPE).map_err(sync::Arc::new);
+        let check = |fragment: &str| {
+            check_link(
+                &client,
+                &resolver,
+                &link,
+                &Some(fragment.to_string()),
+                false,
+                Some(&document),
+            )
+            .map_err(|e| e.tag)
+        };
+        assert_eq!(check("setup"), Err(Tag::DuplicateAnchor));
+        assert_eq!(check("setup-1"), Ok(()));
+        assert_eq!(check("usage"), Ok(()));
+        let err = check_link(
+            &client,
+            &resolver,
+            &link,
+            &Some("setup".to_string()),
+            false,
+            Some(&document),
+        )
+        .unwrap_err();
+        assert!(err
+            .contexts()
+            .any(|context| context == ("siblings", "#setup-1 #setup-2")));
+
+        let buffer = b"# Setup\n# Setup?\n# Usage\n";
+        let document = Document::parse(&buffer[..], &MARKDOWN_CONTENT_TYPE).unwrap();
+        assert!(document.duplicate_anchors.contains("setup"));
+        assert!(!document.duplicate_anchors.contains("usage"));
+    }
+
+    #[test]
+    fn pandoc_id() {
+        assert_eq!(
+            PandocId.to_id("Heading identifiers in HTML", 0),
+            "heading-identifiers-in-html"
+        );
+        assert_eq!(PandocId.to_id("Maître d'hôtel", 0), "maître-dhôtel");
+        assert_eq!(
+            PandocId.to_id("*Dogs*?--in *my* house?", 0),
+            "dogs--in-my-house"
+        );
+        assert_eq!(PandocId.to_id("3. Applications", 0), "applications");
+        assert_eq!(PandocId.to_id("33", 0), "section");
+        assert_eq!(PandocId.to_id("33", 1), "section-1");
+        assert_eq!(PandocId.to_id("v1.2 `code`", 0), "v1.2-code");
+    }
+
+    #[test]
+    fn gitlab_id() {
+        assert_eq!(
+            GitlabId.to_id("This heading has spaces", 0),
+            "this-heading-has-spaces"
+        );
+        assert_eq!(
+            GitlabId.to_id("This -- is -- hyphenated", 0),
+            "this-is-hyphenated"
+        );
+        assert_eq!(GitlabId.to_id("Über `code`!", 0), "über-code");
+        assert_eq!(GitlabId.to_id("Usage", 1), "usage-1");
+    }
+
+    #[test]
+    fn local_anchor_style() {
+        let resolver = FragResolver::new();
+        let link = Link::Path(fs::canonicalize("example_site/path/to/other.md").unwrap());
+        let check = |anchor_style, fragment: &str| {
+            let client = Client::builder().build().anchor_style(anchor_style);
+            let fragment = Some(fragment.to_string());
+            check_link(&client, &resolver, &link, &fragment, false, None).map_err(|e| e.tag)
+        };
+        assert_eq!(check(AnchorStyle::Github, "1-setup----usage"), Ok(()));
+        assert_eq!(check(AnchorStyle::Gitlab, "1-setup-usage"), Ok(()));
+        assert_eq!(
+            check(AnchorStyle::Gitlab, "1-setup----usage"),
+            Err(Tag::NoFragment)
+        );
+        assert_eq!(check(AnchorStyle::Pandoc, "setup----usage"), Ok(()));
+    }
+
+    #[test]
+    fn root_marker() {
+        let mut marker = RootMarker::new("Cargo.toml");
+        assert_eq!(
+            marker.find_for_doc(Path::new("example_site/path/to/other.md")),
+            fs::canonicalize(".").ok()
+        );
+        let mut marker = RootMarker::new("non-existing-marker");
+        assert_eq!(
+            marker.find_for_doc(Path::new("example_site/path/to/other.md")),
+            None
+        );
+    }
+
+    #[test]
+    fn case_sensitive_paths() {
+        assert_eq!(
+            check_case(Path::new("example_site/path/to/other.md")).map_err(|e| e.tag),
+            Ok(())
+        );
+        assert_eq!(
+            check_case(Path::new("example_site/path/to/Other.md")).map_err(|e| e.tag),
+            Err(Tag::WrongCase)
+        );
+        assert_eq!(
+            check_case(Path::new("example_site/Path/to/other.md")).map_err(|e| e.tag),
+            Err(Tag::WrongCase)
+        );
+        assert_eq!(
+            check_case(Path::new("example_site/path/to/non-existing.md")).map_err(|e| e.tag),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn base_url() {
+        let record = Record {
+            doc_path: PathBuf::from("example_site/path/to/absolute.md"),
+            doc_line: 2,
+            doc_col: None,
+            link: "/path/to/other.md#existing".to_string(),
+            text: None,
+            title: None,
+            span: None,
+        };
+        let base_url = Url::parse("https://example.com/site/").unwrap();
+        assert_eq!(
+            record.to_link(&None::<PathBuf>, Some(&base_url)).unwrap(),
+            (
+                Link::Url(Url::parse("https://example.com/site/path/to/other.md").unwrap()),
+                Some("existing".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn multiple_roots() {
+        let record = |link: &str| Record {
+            doc_path: PathBuf::from("example_site/path/to/absolute.md"),
+            doc_line: 2,
+            doc_col: None,
+            link: link.to_string(),
+            text: None,
+            title: None,
+            span: None,
+        };
+        let site = fs::canonicalize("example_site").unwrap();
+        let path = fs::canonicalize("example_site/path").unwrap();
+        let roots = [path.clone(), site.clone()];
+        let other = Link::Path(site.join("path/to/other.md"));
+        let check = |link| record(link).to_link_with_roots(&roots, None).ok();
+        assert_eq!(check("/to/other.md"), Some((other.clone(), None)));
+        assert_eq!(
+            check("/path/to/other.md#existing"),
+            Some((other.clone(), Some("existing".to_string())))
+        );
+        assert_eq!(
+            check("/non-existing.md"),
+            Some((Link::Path(path.join("non-existing.md")), None))
+        );
+        assert_eq!(check("other.md"), Some((other, None)));
+        assert_eq!(
+            record("../../../outside.md")
+                .to_link_with_roots(&roots, None)
+                .map_err(|err| err.tag),
+            Err(Tag::OutsideRoot)
+        );
+    }
+
+    #[test]
+    fn asciidoc_anchors() {
+        let mut ids: Vec<_> = asciidoc_ids(include_str!("../example_site/path/to/asciidoc.adoc"))
+            .into_iter()
+            .collect();
+        ids.sort_unstable();
+        assert_eq!(
+            ids,
+            vec![
+                "_getting_started",
+                "_getting_started_2",
+                "_whats_new_in_1_0",
+                "another",
+                "inline-anchor",
+                "install",
+            ]
+        );
+        assert_eq!(asciidoc_id("Foo - Bar", "", "-"), "foo-bar");
+
+        let resolver = FragResolver::new();
+        let link = Link::Path(fs::canonicalize("example_site/path/to/asciidoc.adoc").unwrap());
+        let client = Client::builder().build();
+        let check = |fragment: &str| {
+            let fragment = Some(fragment.to_string());
+            check_link(&client, &resolver, &link, &fragment, false, None).map_err(|e| e.tag)
+        };
+        assert_eq!(check("_getting_started"), Ok(()));
+        assert_eq!(check("install"), Ok(()));
+        assert_eq!(check("getting-started"), Err(Tag::NoFragment));
+    }
+
+    #[test]
+    fn mdx_links() {
+        let path = "example_site/path/to/component.mdx";
+        let records: Vec<_> = read_mdx(path, false)
+            .unwrap()
+            .map(|record| (record.doc_line, record.doc_col.unwrap(), record.link))
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                (8, 5, "other.md#existing".to_string()),
+                (10, 1, "other.md#non-existing".to_string()),
+                (11, 19, "other.md".to_string()),
+                (14, 1, "non-existing.png".to_string()),
+            ]
+        );
+        let buffer = fs::read_to_string(path).unwrap();
+        let (markdown, _) = mdx_markdown(&buffer);
+        assert_eq!(markdown.len(), buffer.len());
+        assert!(markdown.contains("An expression              and `<Inline>` code."));
+
+        let buffer = "If a < b then\n[x](other.md)\n\nIf a <b then {c\n\n[y](other.md) <br/>\n";
+        let (markdown, links) = mdx_markdown(buffer);
+        assert_eq!(
+            markdown,
+            "If a < b then\n[x](other.md)\n\nIf a <b then {c\n\n[y](other.md)      \n"
+        );
+        assert!(links.is_empty());
+    }
+
+    #[test]
+    fn all_tags() {
+        let codes: HashSet<String> = Tag::ALL.iter().map(Tag::to_string).collect();
+        assert_eq!(codes.len(), Tag::ALL.len());
+        for tag in Tag::ALL {
+            assert_eq!(Tag::from_str(&tag.to_string()).ok(), Some(*tag));
+            assert!(!tag.description().is_empty());
+        }
+        assert!(!Tag::ALL.contains(&Tag::HttpStatus(reqwest::StatusCode::NOT_FOUND)));
+    }
+
+    #[test]
+    fn malformed_links() {
+        let record = |link: &str| Record {
+            doc_path: PathBuf::from("example_site/path/to/example.md"),
+            doc_line: 1,
+            doc_col: None,
+            link: link.to_string(),
+            text: None,
+            title: None,
+            span: None,
+        };
+        let check = |link| {
+            record(link)
+                .to_link(&None::<PathBuf>, None)
+                .map(|_| ())
+                .map_err(|err| {
+                    let trimmed = err
+                        .contexts()
+                        .find(|&(key, _)| key == "trimmed")
+                        .map(|(_, value)| value.to_string());
+                    (err.tag, trimmed)
+                })
+        };
+        let malformed = |trimmed: &str| Err((Tag::MalformedLink, Some(trimmed.to_string())));
+        assert_eq!(
+            check(" http://example.com/ "),
+            malformed("http://example.com/")
+        );
+        assert_eq!(
+            check("http://example.com/\npath"),
+            malformed("http://example.com/path")
+        );
+        assert_eq!(check("other.md\t"), malformed("other.md"));
+        assert_eq!(check("other.md"), Ok(()));
+        assert_eq!(check("my other.md"), Ok(()));
+    }
+
+    #[test]
+    fn same_doc_links() {
+        let record = |link: &str| Record {
+            doc_path: PathBuf::from("example_site/path/to/example.md"),
+            doc_line: 9,
+            doc_col: None,
+            link: link.to_string(),
+            text: None,
+            title: None,
+            span: None,
+        };
+        let resolve = |link| record(link).to_link(&None::<PathBuf>, None).unwrap();
+        let (fragment_only, _) = resolve("#heading");
+        let (explicit, fragment) = resolve("example.md#heading");
+        assert_eq!(fragment_only, explicit);
+        assert_eq!(fragment.as_deref(), Some("heading"));
+        assert!(record("#heading").is_same_doc(&fragment_only));
+        assert!(record("example.md#heading").is_same_doc(&explicit));
+        assert!(record("./example.md").is_same_doc(&resolve("./example.md").0));
+        let (other, _) = resolve("other.md#existing");
+        assert!(!record("other.md#existing").is_same_doc(&other));
+    }
+
+    #[test]
+    fn record_formats() {
+        let parse = |format: &str, line: &str| -> result::Result<_, String> {
+            let record = RecordFormat::from_str(format).unwrap().parse(line)?;
+            Ok((
+                record.doc_path,
+                record.doc_line,
+                record.doc_col,
+                record.link,
+            ))
+        };
+        let expected = |col| -> result::Result<_, String> {
+            Ok((
+                PathBuf::from("docs/a.md"),
+                12,
+                col,
+                "https://example.com/".to_string(),
+            ))
+        };
+        assert_eq!(
+            parse("linky", "docs/a.md:12:3:  https://example.com/"),
+            expected(Some(3))
+        );
+        assert_eq!(
+            parse("tsv", "docs/a.md\t12\thttps://example.com/"),
+            expected(None)
+        );
+        assert_eq!(
+            parse(
+                r"regex:^(?P<path>[^:]+):(?P<line>\d+):(?P<col>\d+):.*?(?P<link>https?://[^\s>]+)",
+                "docs/a.md:12:3:see <https://example.com/>",
+            ),
+            expected(Some(3))
+        );
+        assert!(parse("tsv", "docs/a.md\tx\thttps://example.com/").is_err());
+        assert!(RecordFormat::from_str(r"regex:(?P<path>.*)").is_err());
+        assert!(RecordFormat::from_str("csv").is_err());
+    }
+
+    #[test]
+    fn file_urls() {
+        let record = |link: &str| Record {
+            doc_path: PathBuf::from("example_site/path/to/absolute.md"),
+            doc_line: 2,
+            doc_col: None,
+            link: link.to_string(),
+            text: None,
+            title: None,
+            span: None,
+        };
+        assert_eq!(
+            record("file:///srv/doc/../other%20doc.md#existing")
+                .to_link(&None::<PathBuf>, None)
+                .unwrap(),
+            (
+                Link::Path("/srv/other doc.md".into()),
+                Some("existing".to_string())
+            )
+        );
+        assert_eq!(
+            record("file://localhost/srv/doc.md")
+                .to_link(&None::<PathBuf>, None)
+                .unwrap(),
+            (Link::Path("/srv/doc.md".into()), None)
+        );
+        assert_eq!(
+            record("file://example.com/srv/doc.md")
+                .to_link(&None::<PathBuf>, None)
+                .map_err(|err| err.tag),
+            Err(Tag::Protocol)
+        );
+    }
+
+    #[test]
+    fn without_trailing_slash() {
+        let link = |s| Link::Url(Url::parse(s).unwrap());
+        assert_eq!(
+            link("HTTP://Example.com:80/page/").without_trailing_slash(),
+            link("http://example.com/page")
+        );
+        assert_eq!(
+            link("http://example.com/").without_trailing_slash(),
+            link("http://example.com/")
+        );
+    }
+
+    #[test]
+    fn without_query_params() {
+        let link = |s| Link::Url(Url::parse(s).unwrap());
+        let strip = ["utm_source", "utm_medium"];
+        assert_eq!(
+            link("http://example.com/page?utm_source=a&v=1&utm_medium=b")
+                .without_query_params(&strip),
+            link("http://example.com/page?v=1")
+        );
+        assert_eq!(
+            link("http://example.com/page?utm_source=a").without_query_params(&strip),
+            link("http://example.com/page")
+        );
+        assert_eq!(
+            link("http://example.com/page?v=2").without_query_params(&strip),
+            link("http://example.com/page?v=2")
+        );
+        assert_eq!(
+            link("http://example.com/page?q=a%20b&flag&utm_source=a").without_query_params(&strip),
+            link("http://example.com/page?q=a%20b&flag")
+        );
+        assert_eq!(
+            link("http://example.com/page?utm%5Fmedium=b&x=%2B").without_query_params(&strip),
+            link("http://example.com/page?x=%2B")
+        );
+    }
+
+    #[test]
+    fn idn_hosts() {
+        let url = Url::parse("https://exämple.com/päth#frag").unwrap();
+        let (link, fragment) = Link::from_url(url);
+        assert_eq!(fragment.as_deref(), Some("frag"));
+        match link {
+            Link::Url(ref url) => assert_eq!(url.host_str(), Some("xn--exmple-cua.com")),
+            _ => panic!("expected url link"),
+        }
+        assert_eq!(link.to_string(), "https://exämple.com/p%C3%A4th");
+        assert_eq!(
+            Link::from_url(Url::parse("https://xn--exmple-cua.com/päth").unwrap()).0,
+            link
+        );
+    }
+
+    #[test]
+    fn allow_schemes() {
+        let client = Client::builder()
+            .build()
+            .allow_schemes(vec!["VSCode".to_string()]);
+        let resolver = FragResolver::new();
+        let check = |url| {
+            let (link, fragment) = Link::from_url(Url::parse(url).unwrap());
+            check_link(&client, &resolver, &link, &fragment, false, None).map_err(|err| err.tag)
+        };
+        assert_eq!(check("vscode://file/project#frag"), Ok(()));
+        assert_eq!(check("obsidian://open?vault=notes"), Err(Tag::Protocol));
+    }
+
+    #[test]
+    fn dns_only() {
+        let client = Client::builder().build().dns_only(true);
+        let resolver = FragResolver::new();
+        let check = |url| {
+            let (link, fragment) = Link::from_url(Url::parse(url).unwrap());
+            check_link(&client, &resolver, &link, &fragment, false, None).map_err(|err| err.tag)
+        };
+        assert_eq!(check("http://localhost:1/missing#frag"), Ok(()));
+        assert_eq!(check("https://host.invalid/"), Err(Tag::DnsError));
+        assert_eq!(check("ftp://localhost/"), Err(Tag::Protocol));
+    }
+
+    #[test]
+    fn offline() {
+        let client = Client::builder().build().offline(true);
+        let resolver = FragResolver::new();
+        let check = |link: Link, fragment: Option<&str>| {
+            let fragment = fragment.map(str::to_string);
+            check_link(&client, &resolver, &link, &fragment, false, None).map_err(|err| err.tag)
+        };
+        let url = |url| Link::Url(Url::parse(url).unwrap());
+        let path = Link::Path(fs::canonicalize("example_site/path/to/other.md").unwrap());
+        assert_eq!(
+            check(url("http://localhost:1/"), Some("frag")),
+            Err(Tag::Skipped)
+        );
+        assert_eq!(check(url("tel:+1-201-555-0123"), None), Ok(()));
+        assert_eq!(check(path.clone(), Some("existing")), Ok(()));
+        assert_eq!(check(path, Some("non-existing")), Err(Tag::NoFragment));
+    }
+
+    #[test]
+    fn example_hosts() {
+        let client = Client::builder().build().warn_example_hosts(true);
+        let check = |url| {
+            let link = Link::Url(Url::parse(url).unwrap());
+            client
+                .fetch_link(false, &link)
+                .map(|_| ())
+                .map_err(|err| err.tag)
+        };
+        assert_eq!(check("https://example.com/"), Err(Tag::ExampleHost));
+        assert_eq!(check("https://www.EXAMPLE.org/path"), Err(Tag::ExampleHost));
+        assert_eq!(check("http://docs.test/"), Err(Tag::ExampleHost));
+        assert_eq!(check("http://localhost:1/"), Err(Tag::ExampleHost));
+        assert_eq!(check("http://host.invalid./"), Err(Tag::ExampleHost));
+        assert!(Tag::ExampleHost.is_warning());
+        assert!(!is_example_host(
+            &Url::parse("https://notexample.com/").unwrap()
+        ));
+        assert!(!is_example_host(
+            &Url::parse("https://example.com.se/").unwrap()
+        ));
+        assert!(!is_example_host(&Url::parse("http://127.0.0.1/").unwrap()));
+    }
+
+    #[test]
+    fn deadline() {
+        let timeouts = Timeouts {
+            deadline: Some(Duration::ZERO),
+            ..Timeouts::default()
+        };
+        let client = Client::builder().timeouts(timeouts).build();
+        let link = Link::Url(Url::parse("http://localhost:1/").unwrap());
+        let err = client.fetch_link(false, &link).err().unwrap();
+        assert_eq!(err.tag, Tag::Timeout);
+        assert!(err
+            .contexts()
+            .any(|context| context == ("phase", "deadline")));
+    }
+
+    #[test]
+    fn prefer_https() {
+        let client = Client::builder().build();
+        let resolver = FragResolver::new();
+        let link = Link::Url(Url::parse("http://example.com/").unwrap());
+        let mut document = Document::new();
+        document.https_url = Some(Url::parse("https://example.com/").unwrap());
+        let err = check_link(&client, &resolver, &link, &None, false, Some(&Ok(document)))
+            .err()
+            .unwrap();
+        assert_eq!(err.tag, Tag::InsecureLink);
+        assert!(err.tag.is_warning());
+        assert!(err
+            .contexts()
+            .any(|context| context == ("https", "https://example.com/")));
+    }
+
+    /// Serves a single HTTP response on a local port and returns its URL.
+    /// Binds a test server to a free port on 127.0.0.1 and returns its URL.
+    fn bind() -> (std::net::TcpListener, String) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        (listener, url)
+    }
+
+    /// Answers one connection per response, in order, and returns the
+    /// lowercased requests.
+    fn serve(
+        listener: std::net::TcpListener,
+        responses: Vec<(&'static str, String, Vec<u8>)>,
+    ) -> std::thread::JoinHandle<Vec<String>> {
+        use std::io::Write;
+
+        std::thread::spawn(move || {
+            let mut requests = vec![];
+            for (status_line, headers, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buffer = [0; 4096];
+                let len = stream.read(&mut buffer).unwrap();
+                requests.push(String::from_utf8_lossy(&buffer[..len]).to_lowercase());
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    status_line,
+                    headers,
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+            requests
+        })
+    }
+
+    fn serve_once(
+        status_line: &'static str,
+        headers: &str,
+        body: &[u8],
+    ) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let (listener, url) = bind();
+        let server = serve(
+            listener,
+            vec![(status_line, headers.to_string(), body.to_vec())],
+        );
+        (url, server)
+    }
+
+    #[test]
+    fn benign_redirects() {
+        let benign = |from, to, upgrade_only| {
+            is_benign_redirect(
+                &Url::parse(from).unwrap(),
+                &Url::parse(to).unwrap(),
+                upgrade_only,
+            )
+        };
+        assert!(benign("http://a.com/x?y", "https://a.com/x?y", true));
+        assert!(!benign("http://a.com/x", "https://a.com/z", true));
+        assert!(benign("http://a.com/x", "https://a.com/z", false));
+        assert!(benign("https://a.com/x", "https://a.com/z", false));
+        assert!(!benign("https://a.com/x", "http://a.com/x", false));
+        assert!(!benign("http://a.com/x", "https://www.a.com/x", false));
+        assert!(!benign("https://a.com/x", "https://b.com/x", false));
+    }
+
+    /// Sends a HEAD request with the client and returns the lowercased request.
+    fn request_headers(client: Client) -> String {
+        let (url, server) = serve_once("204 No Content", "", b"");
+        client.head(url.as_str()).unwrap();
+        server.join().unwrap().remove(0)
+    }
+
+    #[test]
+    fn accept_header() {
+        let request = request_headers(Client::builder().build());
+        assert!(request.contains("accept: text/html, text/markdown;q=0.9, */*;q=0.1\r\n"));
+        let client = Client::builder()
+            .accept(HeaderValue::from_static("application/json"))
+            .build();
+        assert!(request_headers(client).contains("accept: application/json\r\n"));
+    }
+
+    #[test]
+    fn host_headers() {
+        let token = HeaderValue::from_static("Bearer secret");
+        let client = Client::builder()
+            .host_header("127.0.0.1", "authorization", token.clone())
+            .build();
+        assert!(request_headers(client).contains("authorization: bearer secret"));
+        let client = Client::builder()
+            .host_header("api.github.com", "authorization", token)
+            .build();
+        assert!(!request_headers(client).contains("authorization"));
+    }
+
+    #[test]
+    fn wayback_fallback() {
+        let (api, api_server) = serve_once(
+            "200 OK",
+            "Content-Type: application/json\r\n",
+            br#"{"archived_snapshots":{"closest":{"available":true,"status":"200","url":"http://web.archive.org/web/2020/http://example.com/"}}}"#,
+        );
+        let (url, server) = serve_once("404 Not Found", "", b"");
+        let client = Client::builder()
+            .build()
+            .wayback_fallback(Some(Url::parse(&api).unwrap()));
+        let (link, _) = Link::from_url(Url::parse(&url).unwrap());
+        let err = client.fetch_link(false, &link).err().unwrap();
+        server.join().unwrap();
+        api_server.join().unwrap();
+        assert_eq!(err.tag, Tag::ArchivedAvailable);
+        assert!(err.contexts().any(|context| context
+            == (
+                "snapshot",
+                "http://web.archive.org/web/2020/http://example.com/"
+            )));
+        assert!(err.contexts().any(|context| context == ("tag", "HTTP_404")));
+
+        let (api, api_server) = serve_once(
+            "200 OK",
+            "Content-Type: application/json\r\n",
+            br#"{"archived_snapshots":{}}"#,
+        );
+        let (url, server) = serve_once("410 Gone", "", b"");
+        let client = Client::builder()
+            .build()
+            .wayback_fallback(Some(Url::parse(&api).unwrap()));
+        let (link, _) = Link::from_url(Url::parse(&url).unwrap());
+        let err = client.fetch_link(false, &link).err().unwrap();
+        server.join().unwrap();
+        api_server.join().unwrap();
+        assert_eq!(err.tag, Tag::HttpStatus(reqwest::StatusCode::GONE));
+    }
+
+    #[test]
+    fn host_headers_across_redirects() {
+        let (other, other_server) = serve_once("204 No Content", "", b"");
+        let (url, server) = serve_once("302 Found", &format!("Location: {other}\r\n"), b"");
+        let url = url.replace("127.0.0.1", "localhost");
+
+        let client = Client::builder()
+            .host_header("localhost", "private-token", HeaderValue::from_static("x"))
+            .build();
+        let (response, redirects) = client.get(url.as_str()).unwrap();
+        server.join().unwrap();
+        let request = other_server.join().unwrap().remove(0);
+        assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+        assert_eq!(redirects.len(), 1);
+        assert_eq!(redirects[0].1.as_str(), other);
+        assert!(request.starts_with("get / "));
+        assert!(!request.contains("private-token"));
+    }
+
+    #[test]
+    fn conditional_requests_across_redirects() {
+        let (other, other_server) = serve_once("304 Not Modified", "", b"");
+        let (url, server) = serve_once("302 Found", &format!("Location: {other}\r\n"), b"");
+        let url = url.replace("127.0.0.1", "localhost");
+        let client = Client::builder()
+            .host_header("localhost", "private-token", HeaderValue::from_static("x"))
+            .build();
+        let cached = Document {
+            etag: Some("\"v1\"".to_string()),
+            ..Document::from(&["cached"])
+        };
+        let (link, _) = Link::from_url(Url::parse(&url).unwrap());
+        let document = client.revalidate(false, &link, &cached).ok().unwrap();
+        server.join().unwrap();
+        let request = other_server.join().unwrap().remove(0);
+        assert!(request.contains("if-none-match: \"v1\"\r\n"));
+        assert!(!request.contains("private-token"));
+        assert!(document.ids.contains("cached"));
+    }
+
+    #[test]
+    fn redirect_loop() {
+        let (listener, url) = bind();
+        let (other, other_server) = serve_once("302 Found", &format!("Location: {url}\r\n"), b"");
+        let server = serve(
+            listener,
+            vec![("302 Found", format!("Location: {other}\r\n"), vec![])],
+        );
+        let client = Client::builder().follow_redirects(true).build();
+        let (link, _) = Link::from_url(Url::parse(&url).unwrap());
+        let err = client.fetch_link(false, &link).err().unwrap();
+        server.join().unwrap();
+        other_server.join().unwrap();
+        assert_eq!(err.tag, Tag::RedirectLoop);
+        assert!(err
+            .contexts()
+            .any(|context| context == ("redirect(302)", other.as_str())));
+        assert!(err
+            .contexts()
+            .any(|context| context == ("redirect(302)", url.as_str())));
+    }
+
+    #[test]
+    fn redirect_loop_across_hosts() {
+        let (listener, url) = bind();
+        let url = url.replace("127.0.0.1", "localhost");
+        let (other, other_server) = serve_once("302 Found", &format!("Location: {url}\r\n"), b"");
+        let server = serve(
+            listener,
+            vec![("302 Found", format!("Location: {other}\r\n"), vec![])],
+        );
+        let client = Client::builder()
+            .host_header("localhost", "private-token", HeaderValue::from_static("x"))
+            .follow_redirects(true)
+            .build();
+        let (link, _) = Link::from_url(Url::parse(&url).unwrap());
+        let err = client.fetch_link(false, &link).err().unwrap();
+        server.join().unwrap();
+        other_server.join().unwrap();
+        assert_eq!(err.tag, Tag::RedirectLoop);
+    }
+
+    #[test]
+    fn accepted_statuses() {
+        let client = Client::builder()
+            .build()
+            .accept_statuses(vec![reqwest::StatusCode::FORBIDDEN]);
+        let resolver = FragResolver::new();
+        let check = |client: &Client, status_line| {
+            let (url, server) =
+                serve_once(status_line, "Content-Type: text/html\r\n", b"<p>ok</p>");
+            let (link, fragment) = Link::from_url(Url::parse(&url).unwrap());
+            let document = client.fetch_link(false, &link);
+            server.join().unwrap();
+            check_link(client, &resolver, &link, &fragment, false, Some(&document))
+                .map_err(|err| err.tag)
+        };
+        assert_eq!(check(&client, "403 Forbidden"), Ok(()));
+        let status = reqwest::StatusCode::from_u16(999).unwrap();
+        assert_eq!(
+            check(&client, "999 Request Denied"),
+            Err(Tag::HttpStatus(status))
+        );
+        let client = client.accept_statuses(vec![status]);
+        assert_eq!(check(&client, "999 Request Denied"), Ok(()));
+    }
+
+    #[test]
+    fn too_many_redirects() {
+        let location = |url: &str| format!("Location: {url}\r\n");
+        let (second, second_server) =
+            serve_once("302 Found", &location("http://127.0.0.1:1/"), b"");
+        let (first, first_server) = serve_once("302 Found", &location(&second), b"");
+        let client = Client::builder()
+            .follow_redirects(true)
+            .max_redirects(1)
+            .build();
+        let (link, _) = Link::from_url(Url::parse(&first).unwrap());
+        let err = client.fetch_link(false, &link).err().unwrap();
+        first_server.join().unwrap();
+        second_server.join().unwrap();
+        assert_eq!(err.tag, Tag::TooManyRedirects);
+        assert!(err.contexts().any(|context| context == ("limit", "1")));
+        assert!(err
+            .contexts()
+            .any(|context| context == ("redirect(302)", second.as_str())));
+    }
+
+    /// Serves a chain of `hops` HTTP redirects or meta refreshes ending in a
+    /// page, and returns its first URL.
+    ///
+    /// The servers are never joined, since those after a failing hop are
+    /// never requested.
+    fn redirect_chain(hops: usize, refresh: bool) -> String {
+        let servers: Vec<_> = (0..=hops).map(|_| bind()).collect();
+        let urls: Vec<String> = servers.iter().map(|(_, url)| url.clone()).collect();
+        for (i, (listener, _)) in servers.into_iter().enumerate() {
+            let response = match urls.get(i + 1) {
+                Some(next) if refresh => (
+                    "200 OK",
+                    "Content-Type: text/html\r\n".to_string(),
+                    format!(r#"<meta http-equiv="Refresh" content="0; URL='{next}'">"#)
+                        .into_bytes(),
+                ),
+                Some(next) => ("302 Found", format!("Location: {next}\r\n"), vec![]),
+                None => (
+                    "200 OK",
+                    "Content-Type: text/html\r\n".to_string(),
+                    b"<p>done</p>".to_vec(),
+                ),
+            };
+            serve(listener, vec![response]);
+        }
+        urls[0].clone()
+    }
+
+    #[test]
+    fn redirect_limit() {
+        let client = Client::builder()
+            .follow_redirects(true)
+            .max_redirects(2)
+            .build();
+        let fetch = |hops, refresh| {
+            let (link, _) = Link::from_url(Url::parse(&redirect_chain(hops, refresh)).unwrap());
+            client.fetch_link(false, &link)
+        };
+        for refresh in [false, true] {
+            let document = fetch(2, refresh).ok().unwrap();
+            assert_eq!(document.redirects.len(), 2);
+            let err = fetch(3, refresh).err().unwrap();
+            assert_eq!(err.tag, Tag::TooManyRedirects);
+            assert!(err.contexts().any(|context| context == ("limit", "2")));
+        }
+    }
+
+    #[test]
+    fn too_many_meta_refreshes() {
+        let refresh = |url: &str| {
+            format!(r#"<meta http-equiv="Refresh" content="0; URL='{url}'">"#).into_bytes()
+        };
+        let (second, second_server) = serve_once(
+            "200 OK",
+            "Content-Type: text/html\r\n",
+            &refresh("http://127.0.0.1:1/?n=2"),
+        );
+        let (first, first_server) = serve_once(
+            "200 OK",
+            "Content-Type: text/html\r\n",
+            &refresh(&format!("{second}?n=1")),
+        );
+        let client = Client::builder()
+            .follow_redirects(true)
+            .max_redirects(1)
+            .build();
+        let (link, _) = Link::from_url(Url::parse(&first).unwrap());
+        let err = client.fetch_link(false, &link).err().unwrap();
+        first_server.join().unwrap();
+        second_server.join().unwrap();
+        assert_eq!(err.tag, Tag::TooManyRedirects);
+        assert!(err.contexts().any(|context| context == ("limit", "1")));
+        assert!(err
+            .contexts()
+            .any(|context| context == ("redirect(200)", "http://127.0.0.1:1/?n=2")));
+    }
+
+    #[test]
+    fn revalidate() {
+        let (url, server) = serve_once("304 Not Modified", "", b"");
+        let cached = Document {
+            etag: Some("\"v1\"".to_string()),
+            ..Document::from(&["cached"])
+        };
+        let client = Client::builder().build();
+        let (link, _) = Link::from_url(Url::parse(&url).unwrap());
+        let document = client.revalidate(false, &link, &cached).ok().unwrap();
+        let request = server.join().unwrap().remove(0);
+        assert!(request.starts_with("head / "));
+        assert!(request.contains("if-none-match: \"v1\"\r\n"));
+        assert!(document.ids.contains("cached"));
+
+        let (url, server) = serve_once(
+            "200 OK",
+            "Content-Type: text/html\r\n",
+            br#"<h1 id="fresh">Fresh</h1>"#,
+        );
+        let (link, _) = Link::from_url(Url::parse(&url).unwrap());
+        let document = client
+            .revalidate(false, &link, &Document::from(&["cached"]))
+            .ok()
+            .unwrap();
+        server.join().unwrap();
+        assert!(document.ids.contains("fresh"));
+        assert!(!document.ids.contains("cached"));
+    }
+
+    #[test]
+    fn compressed_body() {
+        let (url, server) = serve_once(
+            "200 OK",
+            "Content-Type: text/html\r\nContent-Encoding: gzip\r\n",
+            include_bytes!("../example_site/path/to/compressed.html.gz"),
+        );
+        let client = Client::builder().build();
+        let resolver = FragResolver::new();
+        let (link, fragment) = Link::from_url(Url::parse(&format!("{url}#compressed")).unwrap());
+        let document = client.fetch_link(false, &link);
+        server.join().unwrap();
+        assert_eq!(
+            check_link(&client, &resolver, &link, &fragment, false, Some(&document))
+                .map_err(|err| err.tag),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn short_body() {
+        let (url, server) = serve_once("200 OK", "Content-Type: text/html\r\n", b"");
+        let client = Client::builder().build();
+        let (link, _) = Link::from_url(Url::parse(&url).unwrap());
+        let document = client.fetch_link(false, &link);
+        server.join().unwrap();
+        assert!(document.is_ok());
+    }
+
+    #[test]
+    fn bytes_read() {
+        let (url, server) = serve_once("200 OK", "Content-Type: text/html\r\n", b"<p id=a>");
+        let client = Client::builder().build();
+        let (link, _) = Link::from_url(Url::parse(&url).unwrap());
+        let document = client.fetch_link(false, &link);
+        server.join().unwrap();
+        assert_eq!(
+            document.map(|document| document.bytes_read).ok(),
+            Some(Some(8))
+        );
+    }
+
+    #[test]
+    fn pins() {
+        let hello = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let fetch = |client: Client, sha256: &str| {
+            let (url, server) = serve_once("200 OK", "Content-Type: text/plain\r\n", b"hello");
+            let url = Url::parse(&url).unwrap();
+            let client = client.pins(vec![(url.clone(), sha256.to_string())]);
+            let result = client
+                .fetch_link(false, &Link::Url(url))
+                .map(|_| ())
+                .map_err(|err| err.tag);
+            server.join().unwrap();
+            result
+        };
+        let client = || Client::builder().build();
+        assert_eq!(fetch(client(), hello), Ok(()));
+        assert_eq!(fetch(client(), &hello.to_uppercase()), Ok(()));
+        assert_eq!(fetch(client(), &"0".repeat(64)), Err(Tag::ChecksumMismatch));
+        assert_eq!(
+            fetch(client().check_fragments(false), &"0".repeat(64)),
+            Err(Tag::ChecksumMismatch)
+        );
+    }
+
+    #[test]
+    fn expect_content() {
+        let fetch = |client: Client| {
+            let (url, server) = serve_once(
+                "200 OK",
+                "Content-Type: text/html\r\n",
+                b"<title>Oops</title><p>Page <b>not</b> found</p>",
+            );
+            let link = Link::Url(Url::parse(&url).unwrap());
+            let result = client
+                .fetch_link(false, &link)
+                .map(|_| ())
+                .map_err(|err| err.tag);
+            server.join().unwrap();
+            result
+        };
+        let regex = |pattern| vec![Regex::new(pattern).unwrap()];
+        let client = || Client::builder().build();
+        assert_eq!(fetch(client().expect_present(regex("Oops"))), Ok(()));
+        assert_eq!(
+            fetch(client().expect_present(regex("Welcome"))),
+            Err(Tag::ContentMismatch)
+        );
+        assert_eq!(
+            fetch(client().expect_absent(regex("(?i)page not found"))),
+            Err(Tag::ContentMismatch)
+        );
+        assert_eq!(fetch(client().expect_absent(regex("Welcome"))), Ok(()));
+    }
+
+    #[test]
+    fn unify_404() {
+        let fetch = |client: &Client, status_line| {
+            let (url, server) = serve_once(status_line, "", b"");
+            let link = Link::Url(Url::parse(&url).unwrap());
+            let result = client
+                .fetch_link(false, &link)
+                .map(|_| ())
+                .map_err(|err| err.tag);
+            server.join().unwrap();
+            result
+        };
+        let client = Client::builder().build();
+        assert_eq!(
+            fetch(&client, "404 Not Found"),
+            Err(Tag::HttpStatus(reqwest::StatusCode::NOT_FOUND))
+        );
+        let client = Client::builder().build().unify_404(true);
+        assert_eq!(fetch(&client, "404 Not Found"), Err(Tag::NoDocument));
+        assert_eq!(fetch(&client, "410 Gone"), Err(Tag::NoDocument));
+        assert_eq!(
+            fetch(&client, "500 Internal Server Error"),
+            Err(Tag::HttpStatus(reqwest::StatusCode::INTERNAL_SERVER_ERROR))
+        );
+    }
+
+    #[test]
+    fn phone_numbers() {
+        let client = Client::builder().build();
+        let check = |url| {
+            client
+                .fetch_link(false, &Link::Url(Url::parse(url).unwrap()))
+                .map(|_| ())
+                .map_err(|err| err.tag)
+        };
+        assert_eq!(check("tel:+1-555-0100"), Ok(()));
+        assert_eq!(check("tel:+1.(555).0100;ext=12"), Ok(()));
+        assert_eq!(check("tel:7042;phone-context=example.com"), Ok(()));
+        assert_eq!(check("sms:+15550100,+15550101?body=hello"), Ok(()));
+        assert_eq!(check("tel:7042"), Err(Tag::InvalidTel));
+        assert_eq!(check("tel:+1 555 0100"), Err(Tag::InvalidTel));
+        assert_eq!(check("tel:+"), Err(Tag::InvalidTel));
+        assert_eq!(check("sms:+15550100,hello"), Err(Tag::InvalidTel));
+    }
+
+    #[test]
+    fn no_fragment_check() {
+        let client = Client::builder().build();
+        let resolver = FragResolver::new();
+        let url = Link::Url(Url::parse("https://example.com/").unwrap());
+        let path = Link::Path("/doc.md".into());
+        let document = Ok(Document::new());
+        let fragment = Some("missing".to_string());
+        let check = |client: &Client, link| {
+            check_link(client, &resolver, link, &fragment, false, Some(&document))
+                .map_err(|err| err.tag)
+        };
+        assert_eq!(check(&client, &url), Err(Tag::NoFragment));
+        let client = client.check_fragments(false);
+        assert_eq!(check(&client, &url), Ok(()));
+        assert_eq!(check(&client, &path), Err(Tag::NoFragment));
+    }
+
+    #[test]
+    fn link_path() {
+        let doc_path = Path::new("/root/doc/path/to/doc.md");
+        assert_eq!(
+            Link::path("../other.md#frag", &doc_path, &None::<&Path>).ok(),
+            Some((
+                Link::Path("/root/doc/path/other.md".into()),
+                Some("frag".to_string())
+            ))
+        );
+        assert_eq!(
+            Link::path("/./path/../other.md", &doc_path, &Some("/root/doc")).ok(),
+            Some((Link::Path("/root/doc/other.md".into()), None))
+        );
+        assert_eq!(
+            Link::path("../../../../etc/passwd", &doc_path, &Some("/root/doc")).map_err(|e| e.tag),
+            Err(Tag::OutsideRoot)
+        );
+        assert_eq!(
+            Link::path("/../other.md", &doc_path, &Some("/root/doc")).map_err(|e| e.tag),
+            Err(Tag::OutsideRoot)
+        );
+    }
+
+    #[test]
+    fn error_context() {
+        let err = Tag::NoFragment
+            .as_error()
+            .context("fragment", "#foo")
+            .context("link", "other.md");
+        assert_eq!(
+            err.contexts().collect::<Vec<_>>(),
+            vec![("link", "other.md"), ("fragment", "#foo")]
+        );
+        assert_eq!(
+            err.iter().collect::<Vec<_>>(),
+            vec![
+                "Fragment not found",
+                "  context: link = other.md",
+                "  context: fragment = #foo"
+            ]
+        );
+    }
+
+    #[test]
+    fn decoding() {
+        let latin1 = b"\xC4ntligen stod pr\xE4sten i predikstolen.".to_vec();
+        assert_eq!(
+            read_chars(
+                &mut latin1.as_slice(),
+                Some("ISO-8859-1".to_string()),
+                encoding_rs::UTF_8
+            )
+            .ok(),
+            Some("Äntligen stod prästen i predikstolen.".to_string())
+        );
+        assert_eq!(
+            parse_content_type("text/html; charset=utf-8; junk")
+                .ok()
+                .and_then(|mime| mime.get_param(mime::CHARSET).map(|v| v.to_string())),
+            Some("utf-8".to_string())
+        );
+        assert_eq!(
+            parse_content_type("text/html;;charset=x-junk")
+                .ok()
+                .map(|mime| mime.essence_str().to_string()),
+            Some("text/html".to_string())
+        );
         assert_eq!(
-            read_chars(&mut latin1.as_slice(), Some("ISO-8859-1".to_string())).ok(),
+            read_chars(
+                &mut latin1.as_slice(),
+                Some("x-junk".to_string()),
+                encoding_rs::WINDOWS_1252
+            )
+            .ok(),
             Some("Äntligen stod prästen i predikstolen.".to_string())
         );
     }
@@ -1,10 +1,15 @@
 use std::fmt;
+use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
-use std::result;
 
+use url::form_urlencoded;
+use url::Position;
 use url::Url;
 
+use crate::error::Result;
+use crate::error::Tag;
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Link {
     Url(Url),
@@ -18,17 +23,64 @@ impl Link {
         (Link::Url(url), fragment)
     }
 
+    /// Removes a trailing slash from the path of a URL link, unless the path is `/`.
+    ///
+    /// Hosts and default ports are already normalized when URLs are parsed.
+    pub fn without_trailing_slash(self) -> Self {
+        match self {
+            Link::Url(mut url) if url.path().len() > 1 && url.path().ends_with('/') => {
+                let path = url.path().trim_end_matches('/').to_string();
+                url.set_path(&path);
+                Link::Url(url)
+            }
+            link => link,
+        }
+    }
+
+    /// Removes the given query parameters from a URL link.
+    ///
+    /// The remaining parameters are kept byte for byte, and the query is dropped
+    /// entirely once no parameters remain.
+    pub fn without_query_params<S: AsRef<str>>(self, names: &[S]) -> Self {
+        match self {
+            Link::Url(mut url) => {
+                let query = match url.query() {
+                    Some(query) => query.to_string(),
+                    None => return Link::Url(url),
+                };
+                let is_stripped = |segment: &&str| {
+                    form_urlencoded::parse(segment.as_bytes())
+                        .next()
+                        .is_some_and(|(name, _)| names.iter().any(|n| n.as_ref() == name))
+                };
+                let segments: Vec<&str> = query.split('&').collect();
+                if !segments.iter().any(is_stripped) {
+                    return Link::Url(url);
+                }
+                let kept: Vec<&str> = segments.into_iter().filter(|s| !is_stripped(s)).collect();
+                if kept.iter().all(|segment| segment.is_empty()) {
+                    url.set_query(None);
+                } else {
+                    url.set_query(Some(&kept.join("&")));
+                }
+                Link::Url(url)
+            }
+            link => link,
+        }
+    }
+
     pub fn path<P1: AsRef<Path>, P2: AsRef<Path>>(
         link: &str,
         doc_path: &P1,
         base_path: &Option<P2>,
-    ) -> result::Result<(Link, Option<String>), url::ParseError> {
+    ) -> Result<(Link, Option<String>)> {
         let (path, fragment) = if let Some(pos) = link.find('#') {
             (&link[0..pos], Some(&link[pos + 1..]))
         } else {
             (link, None)
         };
-        let path = if Path::new(path).is_absolute() {
+        let is_absolute = Path::new(path).is_absolute();
+        let path = if is_absolute {
             if let Some(base_path) = base_path {
                 base_path.as_ref().join(as_relative(&path))
             } else {
@@ -39,6 +91,16 @@ impl Link {
         } else {
             doc_path.as_ref().with_file_name(path)
         };
+        let path = normalize(&path);
+        if let Some(base_path) = base_path {
+            let in_root = is_absolute || doc_path.as_ref().starts_with(base_path);
+            if in_root && !path.starts_with(base_path) {
+                return Err(Tag::OutsideRoot
+                    .as_error()
+                    .context("root", base_path.as_ref().to_string_lossy().into_owned())
+                    .context("link", path.to_string_lossy().into_owned()));
+            }
+        }
         Ok((
             Link::Path(path),
             fragment.map(std::string::ToString::to_string),
@@ -49,7 +111,19 @@ impl Link {
 impl fmt::Display for Link {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Link::Url(ref url) => write!(f, "{url}"),
+            Link::Url(ref url) => match url.domain() {
+                Some(domain) if domain.split('.').any(|label| label.starts_with("xn--")) => {
+                    let (domain, _) = idna::domain_to_unicode(domain);
+                    write!(
+                        f,
+                        "{}{}{}",
+                        &url[..Position::BeforeHost],
+                        domain,
+                        &url[Position::AfterHost..]
+                    )
+                }
+                _ => write!(f, "{url}"),
+            },
             Link::Path(ref path) => write!(f, "{}", path.to_string_lossy()),
         }
     }
@@ -62,3 +136,21 @@ fn as_relative<P: AsRef<Path>>(path: &P) -> &Path {
     }
     components.as_path()
 }
+
+pub(crate) fn normalize<P: AsRef<Path>>(path: &P) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.as_ref().components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if normalized.file_name().is_some() {
+                    normalized.pop();
+                } else if !normalized.has_root() {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
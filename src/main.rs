@@ -1,74 +1,505 @@
 use std::borrow::Cow;
 use std::cmp;
 use std::collections::hash_map::Entry;
+use std::collections::BTreeMap;
 use std::collections::BinaryHeap;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::fmt;
+use std::env;
 use std::fs;
 use std::io;
 use std::io::BufRead;
+use std::io::IsTerminal;
+use std::io::Write;
 use std::iter;
 use std::iter::FromIterator;
+use std::ops::Range;
+use std::path::Path;
 use std::path::PathBuf;
+use std::process;
 use std::str::FromStr;
 use std::sync::atomic;
+use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
 use clap::Parser;
+use clap::ValueEnum;
+use encoding_rs::Encoding;
 use log::debug;
 use log::error;
+use log::info;
 use log::log_enabled;
 use log::warn;
+use log::LevelFilter;
 use rayon::iter::IntoParallelIterator;
 use rayon::iter::ParallelIterator;
+use regex::Regex;
+use reqwest::header::HeaderName;
+use reqwest::header::HeaderValue;
+use reqwest::StatusCode;
+use serde_json::json;
 use shell_escape::escape;
+use url::Url;
 
-use linky::error::Error;
-use linky::error::Tag;
+use linky::check_link;
+use linky::is_html;
+use linky::is_mdx;
+use linky::is_notebook;
 use linky::link::Link;
+use linky::read_html;
+use linky::read_ipynb;
+use linky::read_manifest;
 use linky::read_md;
+use linky::read_mdx;
+use linky::read_text;
+use linky::read_unused_defs;
+use linky::rewrite_md_links;
+use linky::AnchorIndex;
 use linky::Client;
+use linky::Document;
+use linky::Error;
 use linky::FragResolver;
 use linky::Record;
+use linky::RecordFormat;
+use linky::RootMarker;
+use linky::Tag;
+use linky::Timeouts;
+use linky::DEFAULT_ACCEPT;
+use linky::DEFAULT_MAX_REDIRECTS;
+use linky::DEFAULT_USER_AGENT;
+use linky::WAYBACK_API;
 
 #[derive(Parser, Debug)]
 #[command(version)]
 /// Extract links from Markdown files and check links for brokenness.
 struct Opt {
     #[arg(long, short)]
-    /// Print only links
+    /// Print only links; Same as --output-template '{link}'
     link_only: bool,
 
+    #[arg(long, value_name = "STRING", conflicts_with = "link_only", value_parser = Template::parse)]
+    /// Print each link using placeholders {path}, {line}, {col}, {tag}, {link}, {text}, {title}, {final_url}, {ms}, {start} and {end}; Write {{ and }} for literal braces
+    output_template: Option<Template>,
+
+    #[arg(long, conflicts_with_all = ["link_only", "output_template"])]
+    /// Print the path of each document once, above its links
+    group_by_file: bool,
+
+    #[arg(long, value_name = "FORMAT", default_value = "auto")]
+    /// Input file format
+    input_format: InputFormat,
+
+    #[arg(long)]
+    /// Extract bare URLs from plain text
+    bare_urls: bool,
+
+    #[arg(long)]
+    /// Tag link reference definitions of Markdown documents that no link refers to with UNUSED_DEF; With --check, their URLs are checked too
+    check_unused_defs: bool,
+
     #[arg(long, short)]
     /// Check links
     check: bool,
 
+    #[arg(long, requires = "check", conflicts_with = "follow")]
+    /// Only check that the hosts of URL links resolve, tagging the others with DNS_ERR; Fragments of URL links are not checked
+    dns_only: bool,
+
+    #[arg(long)]
+    /// Only check that URL links exist, using HEAD requests; Fragments of URL links are not checked
+    no_fragment_check: bool,
+
+    #[arg(long, conflicts_with = "dns_only")]
+    /// Tag URL links that require network access with SKIPPED without attempting them; Local links are checked as usual
+    offline: bool,
+
+    #[arg(long, conflicts_with_all = ["dns_only", "offline"])]
+    /// Never build an HTTP client; Tag URL links with SKIPPED, don't check local links, and confirm on stderr that no network requests were issued
+    no_network: bool,
+
+    #[arg(long, value_name = "SCHEME")]
+    /// Treat URL links with this scheme as OK without checking them; Repeat to allow multiple schemes
+    allow_scheme: Vec<String>,
+
     #[arg(long, short)]
     /// Follow HTTP redirects
     follow: bool,
 
+    #[arg(long, conflicts_with = "fix")]
+    /// Keep running and re-check files given as arguments whenever their modification times change, along with the files linking to them; Files are polled twice per second rather than watched with a filesystem notifier; Remote documents and failed fetches are kept in memory, and documents are only fetched again if revalidation shows they have changed
+    watch: bool,
+
+    #[arg(long, requires = "check")]
+    /// Rewrite links tagged PERM_REDIRECT or INSECURE in Markdown files to their verified replacements; Each rewritten FILE is backed up as FILE.bak
+    fix: bool,
+
+    #[arg(long, value_name = "N", requires = "follow", default_value_t = DEFAULT_MAX_REDIRECTS)]
+    /// Give up following redirects, including meta refresh redirects, after this many in a row, tagging the link with TOO_MANY_REDIRECTS
+    max_redirects: usize,
+
+    #[arg(long, requires = "follow")]
+    /// Print the final URL of redirected links
+    show_final_url: bool,
+
+    #[arg(long)]
+    /// Print how many milliseconds fetching the linked document took
+    timings: bool,
+
+    #[arg(long)]
+    /// Include the byte range of each Markdown link in JSON lines output and the {start} and {end} placeholders
+    byte_ranges: bool,
+
+    #[arg(long, requires = "follow")]
+    /// Tag links resolved via permanent redirects with PERM_REDIRECT
+    warn_permanent_redirect: bool,
+
+    #[arg(long)]
+    /// Tag links to reserved example and test hosts, like example.com and *.test, with EXAMPLE_HOST without fetching them
+    warn_example_hosts: bool,
+
+    #[arg(long, value_name = "URL=SHA256", value_parser = parse_pin)]
+    /// Tag URL links whose body doesn't have this hex encoded SHA-256 checksum with CHECKSUM_MISMATCH; Repeat to pin multiple URLs
+    pin: Vec<(Url, String)>,
+
+    #[arg(long, value_name = "REGEX", conflicts_with = "no_fragment_check")]
+    /// Tag remote documents whose text doesn't match this regex with CONTENT_MISMATCH; Repeat to require multiple patterns
+    expect_present: Vec<Regex>,
+
+    #[arg(long, value_name = "REGEX", conflicts_with = "no_fragment_check")]
+    /// Tag remote documents whose text matches this regex with CONTENT_MISMATCH, e.g. to catch soft 404 pages; Repeat to forbid multiple patterns
+    expect_absent: Vec<Regex>,
+
+    #[arg(long)]
+    /// Tag reachable http:// links with INSECURE if their https:// equivalents are reachable too
+    prefer_https: bool,
+
+    #[arg(long)]
+    /// Tag remote links answered with 404 or 410 with NO_DOC, like missing local files
+    unify_404: bool,
+
+    #[arg(long, requires = "check")]
+    /// Tag links that respond with 404 or 410, or whose hosts can't be resolved, with ARCHIVED if the Wayback Machine has a snapshot of them
+    wayback_fallback: bool,
+
+    #[arg(long, conflicts_with = "follow")]
+    /// Accept redirects from http:// to the same URL with https:// without --follow
+    allow_redirect_to_https: bool,
+
+    #[arg(long, conflicts_with = "follow")]
+    /// Accept redirects within the same host without --follow, unless they downgrade from https:// to http://
+    allow_same_host_redirect: bool,
+
+    #[arg(long, value_name = "STRING", default_value = DEFAULT_USER_AGENT)]
+    /// User-Agent header of HTTP requests
+    user_agent: String,
+
+    #[arg(long, value_name = "VALUE", default_value = DEFAULT_ACCEPT, value_parser = parse_header_value)]
+    /// Accept header of HTTP requests
+    accept: HeaderValue,
+
+    #[arg(long, num_args = 2, value_names = ["HOST", "HEADER"])]
+    /// Add a header like "Authorization: Bearer TOKEN" to HTTP requests for URLs with this host; Repeat to add multiple headers
+    header_for: Vec<String>,
+
+    #[arg(long, value_name = "URL", value_parser = parse_proxy)]
+    /// Send HTTP requests through a proxy
+    proxy: Option<reqwest::Proxy>,
+
+    #[arg(long, value_name = "SECONDS")]
+    /// Give up connecting to a host after this many seconds
+    connect_timeout: Option<u64>,
+
+    #[arg(long, value_name = "SECONDS")]
+    /// Give up on an HTTP request after this many seconds [default: 30]
+    read_timeout: Option<u64>,
+
+    #[arg(long, value_name = "SECONDS")]
+    /// Give up on a link after this many seconds, including any redirects
+    deadline: Option<u64>,
+
+    #[arg(long, value_name = "SECONDS")]
+    /// Abandon fetching a URL link after this many seconds even if the request hasn't timed out, tagging it with TIMEOUT
+    watchdog: Option<u64>,
+
+    #[arg(long)]
+    /// Accept invalid TLS certificates; Dangerous!
+    insecure: bool,
+
+    #[arg(long, value_name = "FILE", value_parser = parse_certificate)]
+    /// Trust the root certificate in this PEM file
+    ca_cert: Option<reqwest::Certificate>,
+
     #[arg(long, short)]
     /// URL-decode local links
     urldecode: bool,
 
+    #[arg(long, value_name = "CODE", value_parser = clap::value_parser!(u16).range(100..1000))]
+    /// HTTP status to treat as success; Repeat to accept multiple statuses
+    accept_status: Vec<u16>,
+
+    #[arg(long, value_name = "LABEL", value_parser = parse_encoding, default_value = "utf-8")]
+    /// Decode documents with this charset when it can't be determined
+    charset_fallback: &'static Encoding,
+
+    #[arg(long, value_name = "N")]
+    /// Read at most N bytes of each response body when looking for fragments
+    max_body_bytes: Option<u64>,
+
+    #[arg(long, value_name = "STYLE", default_value = "github")]
+    /// Scheme for generating anchors from Markdown headings
+    anchor_style: AnchorStyle,
+
+    #[arg(long, alias = "warn-ambiguous-anchor")]
+    /// Tag fragments matching the first of several headings with the same anchor with DUP_ANCHOR, listing the anchors of the later ones
+    warn_duplicate_anchors: bool,
+
+    #[arg(long)]
+    /// Tag local links matching files only case-insensitively with WRONG_CASE
+    case_sensitive_paths: bool,
+
+    #[arg(long, value_name = "FILE")]
+    /// Tag local links to files not listed in this manifest with NOT_PUBLISHED
+    manifest: Option<PathBuf>,
+
     #[arg(long, short, value_name = "DIR")]
-    /// Join absolute local links to a document root
-    root: Option<PathBuf>,
+    /// Join absolute local links to a document root; Repeat to try multiple roots in order, using the first one containing the linked document
+    root: Vec<PathBuf>,
+
+    #[arg(long)]
+    /// Treat URLs differing only by a trailing slash as the same document
+    normalize_urls: bool,
+
+    #[arg(long, value_name = "PARAM")]
+    /// Remove this query parameter from URL links before grouping and fetching them, e.g. utm_source; Repeat to strip multiple parameters
+    strip_query: Vec<String>,
+
+    #[arg(long, value_name = "URL", value_parser = parse_base_url)]
+    /// Check root-absolute local links against this base URL instead of --root
+    base_url: Option<Url>,
+
+    #[arg(long, value_name = "FILENAME")]
+    /// Join absolute local links to the nearest ancestor directory containing this file
+    root_marker: Option<PathBuf>,
+
+    #[arg(long, value_name = "NAME", default_values = ["index.html", "README.md"])]
+    /// Check local links to directories against this index file if it exists; Repeat to try multiple names in order
+    index: Vec<String>,
 
     #[arg(long, short, value_name = "STRING")]
     /// Fragment prefix; Repeat to check multiple prefixes
     prefix: Vec<String>,
 
+    #[arg(long)]
+    /// Resolve fragments of links to github.com the way GitHub does, accepting anchors with the user-content- prefix
+    github_compat: bool,
+
+    #[arg(long)]
+    /// Tag fragments missing from remote documents with the NO_FRAG_SOFT warning instead of NO_FRAG
+    fragment_soft_fail: bool,
+
+    #[arg(long, value_name = "FORMAT", default_value = "plain")]
+    /// Output format
+    format: OutputFormat,
+
+    #[arg(long, value_name = "WHEN", default_value = "auto")]
+    /// Colorize status tokens
+    color: ColorMode,
+
     #[arg(long, short, value_name = "TAG")]
     /// Tag to mute; Repeat to mute multiple tags
     mute: Vec<Tag>,
 
-    /// Files to parse
+    #[arg(long, value_name = "GLOB=TAG", value_parser = parse_mute_path)]
+    /// Tag to mute in documents with paths matching GLOB; Repeat to add more rules
+    mute_path: Vec<(glob::Pattern, Tag)>,
+
+    #[arg(long, value_name = "TAG")]
+    /// Tag to show, muting all others; Repeat to show multiple tags
+    only: Vec<Tag>,
+
+    #[arg(long, value_name = "TAG")]
+    /// Exit with a non-zero status if a link gets this tag; Repeat to fail on multiple tags
+    fail_on: Vec<Tag>,
+
+    #[arg(long)]
+    /// Exit with a non-zero status if a link gets any tag other than OK or SKIPPED
+    fail_on_any: bool,
+
+    #[arg(long)]
+    /// Print every tag with its description and exit; Use --format jsonl for one JSON object per tag
+    list_tags: bool,
+
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    /// Stop checking links once N links that aren't muted have failed; Links already being checked are still printed, so slightly more than N failures may be reported
+    max_errors: Option<u64>,
+
+    #[arg(long, requires = "check")]
+    /// Print only links that failed with a tag other than a warning or that make the exit status non-zero, followed by the number of them on stderr; Print nothing if no links failed
+    summary_only: bool,
+
+    #[arg(long)]
+    /// Print the number of checked links to stderr as results complete
+    progress: bool,
+
+    #[arg(long, value_name = "FILE")]
+    /// Write a JSON summary of the run to FILE: link counts per tag, unique hosts, bytes downloaded, wall time and the slowest links
+    report: Option<PathBuf>,
+
+    #[arg(long, short, conflicts_with = "verbose")]
+    /// Suppress log messages
+    quiet: bool,
+
+    #[arg(long, short, action = clap::ArgAction::Count)]
+    /// Log more details; Repeat for more verbosity
+    verbose: u8,
+
+    #[arg(long, value_name = "FORMAT", default_value = "pretty")]
+    /// Format of log messages on stderr
+    log_format: LogFormat,
+
+    #[arg(long, value_name = "FILE")]
+    /// Also parse the files listed in FILE, one per line; Use - for stdin
+    files_from: Option<String>,
+
+    #[arg(long, value_name = "FORMAT", default_value = "linky", value_parser = RecordFormat::from_str)]
+    /// Layout of link records read from stdin: linky, tsv (path, line and link separated by tabs) or regex:PATTERN with the named groups path, line, link and optionally col
+    stdin_format: RecordFormat,
+
+    /// Files to parse; Without any, link records are read from stdin
     file: Vec<String>,
 }
 
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OutputFormat {
+    /// One line per link
+    Plain,
+    /// GitHub Actions workflow commands for failing links
+    Github,
+    /// SARIF 2.1.0 log of failing links
+    Sarif,
+    /// One JSON array of all links, printed once the run is done
+    Json,
+    /// One JSON object per link and line
+    Jsonl,
+}
+
+const GLOB_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+fn parse_mute_path(s: &str) -> Result<(glob::Pattern, Tag), String> {
+    let (pattern, tag) = s
+        .rsplit_once('=')
+        .ok_or_else(|| "expected GLOB=TAG".to_string())?;
+    let pattern = glob::Pattern::new(pattern).map_err(|err| err.to_string())?;
+    let tag = Tag::from_str(tag).map_err(|err| err.to_string())?;
+    Ok((pattern, tag))
+}
+
+fn parse_pin(s: &str) -> Result<(Url, String), String> {
+    let (url, sha256) = s
+        .rsplit_once('=')
+        .ok_or_else(|| "expected URL=SHA256".to_string())?;
+    let url = Url::parse(url).map_err(|err| err.to_string())?;
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("expected a hex encoded SHA-256 checksum".to_string());
+    }
+    Ok((url, sha256.to_string()))
+}
+
+fn parse_header(s: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| "expected NAME: VALUE".to_string())?;
+    let name = HeaderName::from_str(name.trim()).map_err(|err| err.to_string())?;
+    let value = HeaderValue::from_str(value.trim()).map_err(|err| err.to_string())?;
+    Ok((name, value))
+}
+
+fn parse_header_value(s: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(s).map_err(|err| err.to_string())
+}
+
+fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label_no_replacement(label.as_bytes())
+        .ok_or_else(|| format!("unknown charset {label}"))
+}
+
+fn parse_proxy(url: &str) -> reqwest::Result<reqwest::Proxy> {
+    reqwest::Proxy::all(url)
+}
+
+fn parse_base_url(s: &str) -> Result<Url, url::ParseError> {
+    let mut url = Url::parse(s)?;
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    Ok(url)
+}
+
+fn parse_certificate(path: &str) -> Result<reqwest::Certificate, String> {
+    let pem = fs::read(path).map_err(|err| err.to_string())?;
+    reqwest::Certificate::from_pem(&pem).map_err(|err| err.to_string())
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum AnchorStyle {
+    /// GitHub's heading anchors
+    Github,
+    /// GitLab's heading anchors
+    Gitlab,
+    /// Pandoc's auto_identifiers extension
+    Pandoc,
+}
+
+impl From<AnchorStyle> for linky::AnchorStyle {
+    fn from(style: AnchorStyle) -> Self {
+        match style {
+            AnchorStyle::Github => linky::AnchorStyle::Github,
+            AnchorStyle::Gitlab => linky::AnchorStyle::Gitlab,
+            AnchorStyle::Pandoc => linky::AnchorStyle::Pandoc,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum InputFormat {
+    /// Determine the format from the file extension
+    Auto,
+    /// Markdown documents
+    Markdown,
+    /// HTML documents
+    Html,
+    /// Plain text, scanned for http:// and https:// URLs
+    Text,
+    /// Jupyter notebooks, with links extracted from their Markdown cells
+    Ipynb,
+    /// MDX documents, with links extracted from their Markdown and JSX elements
+    Mdx,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum LogFormat {
+    /// Human readable log lines
+    Pretty,
+    /// One JSON object per log message, with its timestamp, level, target and message
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
 struct Item<T> {
     index: usize,
     value: T,
@@ -84,7 +515,7 @@ impl<T> Eq for Item<T> {}
 
 impl<T> PartialOrd for Item<T> {
     fn partial_cmp(&self, rhs: &Self) -> Option<cmp::Ordering> {
-        rhs.index.partial_cmp(&self.index)
+        Some(self.cmp(rhs))
     }
 }
 
@@ -126,87 +557,1106 @@ impl<T, F: Fn(T)> Orderer<T, F> {
             self.current.fetch_add(1, atomic::Ordering::SeqCst);
         }
     }
+
+    /// Outputs the remaining items in order, skipping over missing indices.
+    fn flush(&self) {
+        let mut heap = self.heap.lock().unwrap();
+        while let Some(item) = heap.pop() {
+            (self.f)(item.value);
+        }
+    }
 }
 
-fn group_fragments(
-    mut acc: HashMap<Link, Vec<(usize, Option<String>, Record)>>,
-    link: (usize, (Record, Link, Option<String>)),
-) -> HashMap<Link, Vec<(usize, Option<String>, Record)>> {
-    let (index, (record, base, fragment)) = link;
-    match acc.entry(base) {
-        Entry::Vacant(vacant) => {
-            vacant.insert(vec![(index, fragment, record)]);
+struct Progress {
+    total: usize,
+    checked: atomic::AtomicUsize,
+    broken: atomic::AtomicUsize,
+    tty: bool,
+    last: Mutex<Instant>,
+}
+
+impl Progress {
+    fn new(total: usize) -> Self {
+        Progress {
+            total,
+            checked: atomic::AtomicUsize::new(0),
+            broken: atomic::AtomicUsize::new(0),
+            tty: io::stderr().is_terminal(),
+            last: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn record(&self, res: &Option<Result<(), Arc<Error>>>) {
+        let checked = self.checked.fetch_add(1, atomic::Ordering::SeqCst) + 1;
+        if let Some(Err(ref err)) = res {
+            if !err.tag.is_warning() {
+                self.broken.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+        }
+        // Redraw a terminal often, but keep logs readable
+        let interval = if self.tty {
+            Duration::from_millis(100)
+        } else {
+            Duration::from_secs(5)
+        };
+        let mut last = self.last.lock().unwrap();
+        if checked == self.total || last.elapsed() >= interval {
+            *last = Instant::now();
+            self.print();
+        }
+    }
+
+    fn print(&self) {
+        let status = format!(
+            "checked {}/{} ({} broken)",
+            self.checked.load(atomic::Ordering::SeqCst),
+            self.total,
+            self.broken.load(atomic::Ordering::SeqCst)
+        );
+        if self.tty {
+            eprint!("\r{status}");
+            if self.checked.load(atomic::Ordering::SeqCst) == self.total {
+                eprintln!();
+            }
+        } else {
+            eprintln!("{status}");
+        }
+    }
+}
+
+/// The number of slowest linked documents listed in a --report.
+const REPORT_SLOWEST: usize = 10;
+
+#[derive(Default)]
+struct Report {
+    links: usize,
+    tags: BTreeMap<String, usize>,
+    hosts: HashSet<String>,
+    bytes: u64,
+    slowest: Vec<(Duration, Link)>,
+}
+
+impl Report {
+    fn record(&mut self, res: &Option<Result<(), Arc<Error>>>) {
+        self.links += 1;
+        let tag = match res {
+            Some(Ok(())) => Tag::Ok,
+            Some(Err(err)) => err.tag,
+            None => return,
+        };
+        *self.tags.entry(tag.to_string()).or_default() += 1;
+    }
+
+    fn record_document(
+        &mut self,
+        link: &Link,
+        document: &Result<Document, Arc<Error>>,
+        elapsed: Duration,
+    ) {
+        if let Link::Url(url) = link {
+            if let Some(host) = url.host_str() {
+                self.hosts.insert(host.to_string());
+            }
         }
-        Entry::Occupied(mut occupied) => {
-            occupied.get_mut().push((index, fragment, record));
+        if let Ok(document) = document {
+            self.bytes += document.bytes_read.unwrap_or_default();
         }
+        self.slowest.push((elapsed, link.clone()));
+        self.slowest
+            .sort_by_key(|&(elapsed, _)| cmp::Reverse(elapsed));
+        self.slowest.truncate(REPORT_SLOWEST);
+    }
+
+    fn to_json(&self, elapsed: Duration) -> serde_json::Value {
+        json!({
+            "links": self.links,
+            "tags": self.tags,
+            "hosts": self.hosts.len(),
+            "bytes": self.bytes,
+            "ms": elapsed.as_millis() as u64,
+            "slowest": self
+                .slowest
+                .iter()
+                .map(|(elapsed, link)| json!({
+                    "link": link.to_string(),
+                    "ms": elapsed.as_millis() as u64,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+type Outcome = (
+    Record,
+    Option<Result<(), Arc<Error>>>,
+    Option<Url>,
+    Option<Duration>,
+);
+
+type LinkParts = (Link, Option<String>);
+
+type Groups = (
+    HashMap<Link, Vec<(usize, Option<String>, Record)>>,
+    Vec<Item<Outcome>>,
+);
+
+fn group_fragments(
+    mut acc: Groups,
+    link: (usize, (Record, linky::error::Result<LinkParts>)),
+) -> Groups {
+    let (index, (record, res)) = link;
+    match res {
+        Ok((base, fragment)) => match acc.0.entry(base) {
+            Entry::Vacant(vacant) => {
+                vacant.insert(vec![(index, fragment, record)]);
+            }
+            Entry::Occupied(mut occupied) => {
+                occupied.get_mut().push((index, fragment, record));
+            }
+        },
+        Err(err) => acc.1.push(Item {
+            index,
+            value: (record, Some(Err(Arc::new(err))), None, None),
+        }),
     };
     acc
 }
 
-fn print_result(
+fn context_properties(err: &Error) -> serde_json::Map<String, serde_json::Value> {
+    let mut properties = serde_json::Map::new();
+    for (key, value) in err.contexts() {
+        properties
+            .entry(key)
+            .or_insert_with(|| serde_json::Value::from(value));
+    }
+    properties
+}
+
+fn json_record(
     record: &Record,
     res: &Option<Result<(), Arc<Error>>>,
-    silence: &HashSet<&Tag>,
-    link_only: bool,
-) {
-    let tag = res
-        .as_ref()
-        .map(|res| res.as_ref().err().map(|err| err.tag).unwrap_or(Tag::Ok));
+    final_url: &Option<Url>,
+    elapsed: Option<Duration>,
+    span: Option<&Range<usize>>,
+) -> serde_json::Value {
+    let (tag, context) = match *res {
+        Some(Ok(())) => (Some(Tag::Ok.to_string()), serde_json::Map::new()),
+        Some(Err(ref err)) => (Some(err.tag.to_string()), context_properties(err)),
+        None => (None, serde_json::Map::new()),
+    };
+    json!({
+        "path": record.doc_path.to_string_lossy(),
+        "line": record.doc_line,
+        "col": record.doc_col,
+        "tag": tag,
+        "link": record.link,
+        "final_url": final_url.as_ref().map(Url::as_str),
+        "ms": elapsed.map(|elapsed| elapsed.as_millis() as u64),
+        "start": span.map(|span| span.start),
+        "end": span.map(|span| span.end),
+        "text": record.text,
+        "title": record.title,
+        "context": context,
+    })
+}
+
+fn sarif_log(results: &[(Record, Arc<Error>)]) -> serde_json::Value {
+    let mut rules: Vec<Tag> = vec![];
+    for (_, err) in results {
+        if !rules.contains(&err.tag) {
+            rules.push(err.tag);
+        }
+    }
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "linky",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                    "rules": rules.iter().map(|tag| json!({
+                        "id": tag.to_string(),
+                        "shortDescription": { "text": tag.as_error().to_string() },
+                    })).collect::<Vec<_>>(),
+                },
+            },
+            "results": results.iter().map(|(record, err)| json!({
+                "ruleId": err.tag.to_string(),
+                "level": if err.tag.is_warning() { "warning" } else { "error" },
+                "message": { "text": format!("{}: {}", err, record.link) },
+                "properties": context_properties(err),
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": record.doc_path.to_string_lossy() },
+                        "region": match record.doc_col {
+                            Some(col) => json!({ "startLine": record.doc_line, "startColumn": col }),
+                            None => json!({ "startLine": record.doc_line }),
+                        },
+                    },
+                }],
+            })).collect::<Vec<_>>(),
+        }],
+    })
+}
+
+fn escape_github_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
 
-    if !tag.as_ref().map_or(false, |tag| silence.contains(&tag)) {
+fn escape_github_property(s: &str) -> String {
+    escape_github_data(s)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    Path,
+    Line,
+    Col,
+    Tag,
+    Link,
+    Text,
+    Title,
+    FinalUrl,
+    Ms,
+    Start,
+    End,
+}
+
+#[derive(Clone, Debug)]
+enum Piece {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Clone, Debug)]
+struct Template(Vec<Piece>);
+
+impl Template {
+    fn parse(s: &str) -> Result<Self, String> {
+        let mut pieces = vec![];
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('{') if name.is_empty() => {
+                                literal.push('{');
+                                break;
+                            }
+                            Some('}') => {
+                                let field = match name.as_str() {
+                                    "path" => Field::Path,
+                                    "line" => Field::Line,
+                                    "col" => Field::Col,
+                                    "tag" => Field::Tag,
+                                    "link" => Field::Link,
+                                    "text" => Field::Text,
+                                    "title" => Field::Title,
+                                    "final_url" => Field::FinalUrl,
+                                    "ms" => Field::Ms,
+                                    "start" => Field::Start,
+                                    "end" => Field::End,
+                                    _ => return Err(format!("unknown placeholder {{{name}}}")),
+                                };
+                                if !literal.is_empty() {
+                                    pieces.push(Piece::Literal(literal.split_off(0)));
+                                }
+                                pieces.push(Piece::Field(field));
+                                break;
+                            }
+                            Some(c) => name.push(c),
+                            None => return Err("unterminated placeholder".to_string()),
+                        }
+                    }
+                }
+                '}' => {
+                    if chars.next() != Some('}') {
+                        return Err("unmatched '}'; write '}}' for a literal brace".to_string());
+                    }
+                    literal.push('}');
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(Template(pieces))
+    }
+
+    fn render(
+        &self,
+        record: &Record,
+        tag: &str,
+        final_url: &Option<Url>,
+        elapsed: Option<Duration>,
+        span: Option<&Range<usize>>,
+    ) -> String {
+        let mut output = String::new();
+        for piece in &self.0 {
+            match *piece {
+                Piece::Literal(ref literal) => output.push_str(literal),
+                Piece::Field(Field::Path) => output.push_str(&record.doc_path.to_string_lossy()),
+                Piece::Field(Field::Line) => output.push_str(&record.doc_line.to_string()),
+                Piece::Field(Field::Col) => {
+                    if let Some(col) = record.doc_col {
+                        output.push_str(&col.to_string());
+                    }
+                }
+                Piece::Field(Field::Tag) => output.push_str(tag),
+                Piece::Field(Field::Link) => output.push_str(&record.link),
+                Piece::Field(Field::Text) => {
+                    output.push_str(record.text.as_deref().unwrap_or_default())
+                }
+                Piece::Field(Field::Title) => {
+                    output.push_str(record.title.as_deref().unwrap_or_default())
+                }
+                Piece::Field(Field::FinalUrl) => {
+                    if let Some(ref url) = *final_url {
+                        output.push_str(url.as_str());
+                    }
+                }
+                Piece::Field(Field::Ms) => {
+                    if let Some(elapsed) = elapsed {
+                        output.push_str(&elapsed.as_millis().to_string());
+                    }
+                }
+                Piece::Field(Field::Start) => {
+                    if let Some(span) = span {
+                        output.push_str(&span.start.to_string());
+                    }
+                }
+                Piece::Field(Field::End) => {
+                    if let Some(span) = span {
+                        output.push_str(&span.end.to_string());
+                    }
+                }
+            }
+        }
+        output
+    }
+}
+
+type Fixes = HashMap<PathBuf, HashMap<(usize, usize, String), String>>;
+
+/// The maximum number of fetch threads, abandoned or not, kept by `fetch_watched`.
+const WATCHDOG_THREADS: usize = 64;
+
+static WATCHDOG_RUNNING: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+
+/// Fetches a URL link on a separate thread, abandoning it after `watchdog`.
+///
+/// An abandoned fetch keeps running in the background until its own timeouts
+/// expire, but the calling worker is free to move on to other links. Once
+/// `WATCHDOG_THREADS` fetch threads are running, links are fetched on the
+/// calling worker instead.
+///
+/// The `Orderer` prints results in the order of their records, holding back
+/// each result until all earlier ones are pushed. An abandoned fetch still
+/// returns a TIMEOUT error right away, which is pushed for every record of
+/// the link like any other result, so the `Orderer` never waits for a record
+/// whose fetch was abandoned. The late result of the fetch is dropped.
+fn fetch_watched(
+    client: &Arc<Client>,
+    urldecode: bool,
+    link: &Link,
+    watchdog: Option<Duration>,
+) -> Result<Document<'static>, Arc<Error>> {
+    match (watchdog, link) {
+        (Some(watchdog), Link::Url(_)) => {
+            let (client, thread_link) = (Arc::clone(client), link.clone());
+            watched(watchdog, link, move || {
+                client.fetch_link(urldecode, &thread_link)
+            })
+        }
+        _ => client.fetch_link(urldecode, link),
+    }
+}
+
+/// Counts a running watchdog thread for as long as it lives, even if it panics.
+struct WatchdogThread;
+
+impl Drop for WatchdogThread {
+    fn drop(&mut self) {
+        WATCHDOG_RUNNING.fetch_sub(1, atomic::Ordering::SeqCst);
+    }
+}
+
+/// Runs `f` for `link` on a separate thread, giving up on it after `watchdog`.
+///
+/// A panic in `f` is reported as IO_ERR.
+fn watched<T, F>(watchdog: Duration, link: &Link, f: F) -> Result<T, Arc<Error>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Arc<Error>> + Send + 'static,
+{
+    let is_full = WATCHDOG_RUNNING.fetch_add(1, atomic::Ordering::SeqCst) >= WATCHDOG_THREADS;
+    let running = WatchdogThread;
+    if is_full {
+        drop(running);
+        return f();
+    }
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _running = running;
+        // Sending fails if the fetch has been abandoned
+        let _ = sender.send(f());
+    });
+    let err = match receiver.recv_timeout(watchdog) {
+        Ok(result) => return result,
+        Err(mpsc::RecvTimeoutError::Timeout) => Tag::Timeout.as_error(),
+        // The thread panicked without sending a result
+        Err(mpsc::RecvTimeoutError::Disconnected) => Tag::IoError.as_error(),
+    };
+    Err(Arc::new(
+        err.context("phase", "watchdog")
+            .context("link", link.to_string()),
+    ))
+}
+
+/// Returns the verified replacement of a link, if it has a clear canonical one.
+///
+/// Links followed via permanent redirects are replaced by their final URLs,
+/// but only if every redirect of the chain is permanent. Insecure links are
+/// replaced by their `https:` equivalents. Fragments are carried over.
+fn replacement(record: &Record, err: &Error) -> Option<String> {
+    let mut url: Url = record.link.parse().ok()?;
+    let target = match err.tag {
+        Tag::PermanentRedirect => {
+            let redirects: Vec<_> = err
+                .contexts()
+                .filter(|(key, _)| key.starts_with("redirect("))
+                .collect();
+            if !redirects
+                .iter()
+                .all(|&(key, _)| key == "redirect(301)" || key == "redirect(308)")
+            {
+                return None;
+            }
+            redirects.last()?.1
+        }
+        Tag::InsecureLink => err.contexts().find(|&(key, _)| key == "https")?.1,
+        _ => return None,
+    };
+    let fragment = url.fragment().map(str::to_string);
+    url = target.parse().ok()?;
+    if url.fragment().is_none() {
+        url.set_fragment(fragment.as_deref());
+    }
+    Some(url.to_string())
+}
+
+fn fix_file(
+    path: &Path,
+    replacements: &HashMap<(usize, usize, String), String>,
+    bare_urls: bool,
+) -> io::Result<()> {
+    // Rewrite the raw bytes so that the file keeps its encoding, which
+    // rules out anything but UTF-8; a UTF-8 byte order mark is kept as is
+    let bytes = fs::read(path)?;
+    let (bom, text) = match bytes.strip_prefix(b"\xEF\xBB\xBF") {
+        Some(text) => (&bytes[..3], text),
+        None => (&[][..], &bytes[..]),
+    };
+    if bytes.starts_with(b"\xFF\xFE") || bytes.starts_with(b"\xFE\xFF") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "refusing to rewrite UTF-16 text",
+        ));
+    }
+    let buffer = str::from_utf8(text).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("refusing to rewrite invalid UTF-8: {err}"),
+        )
+    })?;
+    let (rewritten, count) = rewrite_md_links(buffer, bare_urls, |link| {
+        replacements
+            .get(&(link.line, link.col, link.url.to_string()))
+            .cloned()
+    });
+    if count > 0 {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".bak");
+        fs::copy(path, &backup)?;
+        fs::write(path, [bom, rewritten.as_bytes()].concat())?;
+        info!(
+            "fixed {} of {} links in {}",
+            count,
+            replacements.len(),
+            path.to_string_lossy()
+        );
+    }
+    Ok(())
+}
+
+fn list_tags(format: OutputFormat) {
+    let http_status = ("HTTP_<status>".to_string(), "unexpected http status", false);
+    let tags = Tag::ALL
+        .iter()
+        .map(|tag| (tag.to_string(), tag.description(), tag.is_warning()))
+        .chain(iter::once(http_status));
+    let mut values = vec![];
+    for (tag, description, is_warning) in tags {
+        let value = json!({
+            "tag": tag,
+            "description": description,
+            "warning": is_warning,
+        });
+        match format {
+            OutputFormat::Jsonl => println!("{value}"),
+            OutputFormat::Json => values.push(value),
+            _ => println!("{tag:<20} {description}"),
+        }
+    }
+    if let OutputFormat::Json = format {
+        println!("{:#}", serde_json::Value::from(values));
+    }
+}
+
+fn input_format(format: InputFormat, path: &str) -> InputFormat {
+    match format {
+        InputFormat::Auto if is_html(path) => InputFormat::Html,
+        InputFormat::Auto if is_notebook(path) => InputFormat::Ipynb,
+        InputFormat::Auto if is_mdx(path) => InputFormat::Mdx,
+        InputFormat::Auto => InputFormat::Markdown,
+        format => format,
+    }
+}
+
+fn position(record: &Record) -> String {
+    match record.doc_col {
+        Some(col) => format!("{}:{}", record.doc_line, col),
+        None => record.doc_line.to_string(),
+    }
+}
+
+fn paint(tag: &Tag, color: bool) -> String {
+    if !color {
+        tag.to_string()
+    } else if *tag == Tag::Ok {
+        format!("\x1b[32m{tag}\x1b[0m")
+    } else if tag.is_warning() {
+        format!("\x1b[33m{tag}\x1b[0m")
+    } else {
+        format!("\x1b[31m{tag}\x1b[0m")
+    }
+}
+
+struct Printer<'a> {
+    silence: HashSet<&'a Tag>,
+    silence_paths: &'a [(glob::Pattern, Tag)],
+    only: HashSet<&'a Tag>,
+    format: OutputFormat,
+    template: Option<Template>,
+    byte_ranges: bool,
+    color: bool,
+    group_by_file: bool,
+    last_path: Mutex<Option<PathBuf>>,
+    sarif_results: Mutex<Vec<(Record, Arc<Error>)>>,
+    json_records: Mutex<Vec<serde_json::Value>>,
+    fail_on: HashSet<&'a Tag>,
+    fail_on_any: bool,
+    failed: atomic::AtomicBool,
+    summary_only: bool,
+    broken: atomic::AtomicUsize,
+    log_format: LogFormat,
+}
+
+impl<'a> Printer<'a> {
+    fn is_muted(&self, record: &Record, tag: &Tag) -> bool {
+        self.silence.contains(tag)
+            || self.silence_paths.iter().any(|(pattern, muted)| {
+                muted == tag && pattern.matches_path_with(&record.doc_path, GLOB_OPTIONS)
+            })
+            || (!self.only.is_empty() && !self.only.contains(tag))
+    }
+
+    fn print(
+        &self,
+        record: &Record,
+        res: &Option<Result<(), Arc<Error>>>,
+        final_url: &Option<Url>,
+        elapsed: Option<Duration>,
+    ) {
+        let tag = res
+            .as_ref()
+            .map(|res| res.as_ref().err().map(|err| err.tag).unwrap_or(Tag::Ok));
+
+        let fails_run = tag.as_ref().is_some_and(|tag| {
+            (self.fail_on_any && *tag != Tag::Ok && *tag != Tag::Skipped)
+                || self.fail_on.contains(&tag)
+        });
+        if fails_run {
+            self.failed.store(true, atomic::Ordering::SeqCst);
+        }
+        if tag.as_ref().is_some_and(|tag| self.is_muted(record, tag)) {
+            return;
+        }
+        // Links that make the exit status non-zero are always shown
+        if self.summary_only {
+            if !fails_run && !tag.is_some_and(|tag| tag != Tag::Ok && !tag.is_warning()) {
+                return;
+            }
+            self.broken.fetch_add(1, atomic::Ordering::SeqCst);
+        }
         if let Some(Err(ref err)) = res {
-            for line in err.iter() {
-                warn!("{}", line);
+            // Keep each error in a single structured log record
+            if let LogFormat::Json = self.log_format {
+                warn!("{}", err.iter().collect::<Vec<_>>().join("\n"));
+            } else {
+                for line in err.iter() {
+                    warn!("{}", line);
+                }
             }
         }
-        if link_only {
-            println!("{}", record.link);
+        if let OutputFormat::Github = self.format {
+            if let Some(Err(ref err)) = res {
+                println!(
+                    "::{} file={},line={}{}::{}",
+                    if err.tag.is_warning() {
+                        "warning"
+                    } else {
+                        "error"
+                    },
+                    escape_github_property(&record.doc_path.to_string_lossy()),
+                    record.doc_line,
+                    record
+                        .doc_col
+                        .map(|col| format!(",col={col}"))
+                        .unwrap_or_default(),
+                    escape_github_data(&format!("{}: {}", err, record.link))
+                );
+            }
+            return;
+        }
+        if let OutputFormat::Sarif = self.format {
+            if let Some(Err(ref err)) = res {
+                self.sarif_results
+                    .lock()
+                    .unwrap()
+                    .push((record.clone(), err.clone()));
+            }
+            return;
+        }
+        let span = record.span.clone().filter(|_| self.byte_ranges);
+        if let OutputFormat::Jsonl = self.format {
+            println!(
+                "{}",
+                json_record(record, res, final_url, elapsed, span.as_ref())
+            );
+            return;
+        }
+        if let OutputFormat::Json = self.format {
+            self.json_records.lock().unwrap().push(json_record(
+                record,
+                res,
+                final_url,
+                elapsed,
+                span.as_ref(),
+            ));
+            return;
+        }
+        let tag = tag
+            .as_ref()
+            .map(|tag| paint(tag, self.color))
+            .unwrap_or_default();
+        if let Some(ref template) = self.template {
+            println!(
+                "{}",
+                template.render(record, &tag, final_url, elapsed, span.as_ref())
+            );
+            return;
+        }
+        let final_url = final_url
+            .as_ref()
+            .map(|url| format!(" -> {url}"))
+            .unwrap_or_default();
+        let elapsed = elapsed
+            .map(|elapsed| format!(" ({}ms)", elapsed.as_millis()))
+            .unwrap_or_default();
+        if self.group_by_file {
+            let mut last_path = self.last_path.lock().unwrap();
+            if last_path.as_ref() != Some(&record.doc_path) {
+                println!("{}:", record.doc_path.to_string_lossy());
+                *last_path = Some(record.doc_path.clone());
+            }
+            println!(
+                "  {}: {} {}{}{}",
+                position(record),
+                tag,
+                record.link,
+                final_url,
+                elapsed
+            );
         } else {
             println!(
-                "{}:{}: {} {}",
+                "{}:{}: {} {}{}{}",
                 record.doc_path.to_string_lossy(),
-                record.doc_line,
-                tag.as_ref()
-                    .map(|tag| tag as &dyn fmt::Display)
-                    .unwrap_or(&"" as &dyn fmt::Display),
-                record.link
+                position(record),
+                tag,
+                record.link,
+                final_url,
+                elapsed
             );
         }
     }
+
+    fn finish(&self) -> bool {
+        if let OutputFormat::Sarif = self.format {
+            let results = self.sarif_results.lock().unwrap();
+            println!("{:#}", sarif_log(&results));
+        }
+        if let OutputFormat::Json = self.format {
+            let records = self.json_records.lock().unwrap().split_off(0);
+            println!("{:#}", serde_json::Value::from(records));
+        }
+        let broken = self.broken.load(atomic::Ordering::SeqCst);
+        if self.summary_only && broken > 0 {
+            let plural = if broken == 1 { "" } else { "s" };
+            eprintln!("{broken} broken link{plural}");
+        }
+        !self.failed.load(atomic::Ordering::SeqCst)
+    }
+}
+
+fn init_logger(quiet: bool, verbose: u8, format: LogFormat) {
+    let mut builder = pretty_env_logger::formatted_builder();
+    if let LogFormat::Json = format {
+        builder.format(|buf, record| {
+            let value = json!({
+                "timestamp": buf.timestamp_millis().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{value}")
+        });
+    }
+    if let Ok(filters) = env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    if quiet {
+        builder.filter_level(LevelFilter::Off);
+    } else if verbose > 0 {
+        builder.filter_level(match verbose {
+            1 => LevelFilter::Warn,
+            2 => LevelFilter::Info,
+            _ => LevelFilter::Debug,
+        });
+    }
+    builder.init();
 }
 
 fn main() {
-    pretty_env_logger::init();
     let opt = Opt::parse();
-    let silence: HashSet<_> = opt.mute.iter().collect();
+    init_logger(opt.quiet, opt.verbose, opt.log_format);
+    if opt.list_tags {
+        list_tags(opt.format);
+        return;
+    }
+
+    if opt.insecure {
+        eprintln!("WARNING: TLS certificate verification is disabled by --insecure");
+    }
+
+    let files = input_files(&opt);
+    if opt.watch {
+        if files.is_empty() {
+            error!("--watch requires files to watch");
+            process::exit(2);
+        }
+        watch(&opt, &files);
+    }
+    if !run(&opt, &files, None) {
+        process::exit(1);
+    }
+}
+
+/// Files given as arguments followed by the ones listed by --files-from.
+fn input_files(opt: &Opt) -> Vec<String> {
+    let mut files = opt.file.clone();
+    if let Some(ref files_from) = opt.files_from {
+        let list = if files_from == "-" {
+            io::read_to_string(io::stdin())
+        } else {
+            fs::read_to_string(files_from)
+        };
+        match list {
+            Ok(list) => files.extend(
+                list.lines()
+                    .filter(|line| !line.is_empty())
+                    .map(String::from),
+            ),
+            Err(err) => {
+                error!(
+                    "reading file list {}: {}",
+                    escape(Cow::Borrowed(files_from)),
+                    err
+                );
+                process::exit(2);
+            }
+        }
+    }
+    files
+}
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Checks the files once and then again whenever any of them is modified.
+///
+/// Files are polled for changes. Only the modified files and the files with
+/// local links to them are checked again.
+fn watch(opt: &Opt, files: &[String]) -> ! {
+    let cache = Mutex::new(HashMap::new());
+    let roots: Vec<PathBuf> = opt
+        .root
+        .iter()
+        .map(|root| fs::canonicalize(root).unwrap())
+        .collect();
+    let canonical: Vec<_> = files
+        .iter()
+        .map(|path| fs::canonicalize(path).ok())
+        .collect();
+    let modified = || -> Vec<_> {
+        files
+            .iter()
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    };
+    let mut mtimes = modified();
+    run(opt, files, Some(&cache));
+    eprintln!("NOTE: watching {} files for changes (--watch)", files.len());
+    loop {
+        thread::sleep(WATCH_INTERVAL);
+        let current = modified();
+        let changed: HashSet<&PathBuf> = canonical
+            .iter()
+            .zip(mtimes.iter().zip(&current))
+            .filter(|(_, (before, after))| before != after)
+            .filter_map(|(path, _)| path.as_ref())
+            .collect();
+        mtimes = current;
+        if changed.is_empty() {
+            continue;
+        }
+        let selected: Vec<String> = files
+            .iter()
+            .zip(&canonical)
+            .filter(|(path, canonical)| {
+                canonical
+                    .as_ref()
+                    .is_some_and(|path| changed.contains(path))
+                    || links_to(opt, &roots, path, &changed)
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+        eprintln!("NOTE: re-checking {} files (--watch)", selected.len());
+        run(opt, &selected, Some(&cache));
+    }
+}
+
+/// Reads the link records of a file in its input format.
+///
+/// Markdown headings are recorded in `anchor_index`, if there is one.
+fn read_records(
+    opt: &Opt,
+    anchor_index: Option<&AnchorIndex>,
+    path: &str,
+) -> io::Result<Box<dyn Iterator<Item = Record>>> {
+    match input_format(opt.input_format, path) {
+        InputFormat::Html => read_html(path),
+        InputFormat::Text => read_text(path),
+        InputFormat::Ipynb => read_ipynb(path, opt.bare_urls),
+        InputFormat::Mdx => read_mdx(path, opt.bare_urls),
+        _ => match anchor_index {
+            Some(anchor_index) => anchor_index.read_md(path, opt.bare_urls),
+            None => read_md(path, opt.bare_urls),
+        },
+    }
+}
+
+/// Whether the document at `path` has a local link into any of `targets`.
+fn links_to(opt: &Opt, roots: &[PathBuf], path: &str, targets: &HashSet<&PathBuf>) -> bool {
+    read_records(opt, None, path).is_ok_and(|mut records| {
+        records.any(
+            |record| match record.to_link_with_roots(roots, opt.base_url.as_ref()) {
+                Ok((Link::Path(target), _)) => {
+                    fs::canonicalize(target).is_ok_and(|target| targets.contains(&target))
+                }
+                _ => false,
+            },
+        )
+    })
+}
+
+/// Results of fetching remote documents in earlier runs in --watch mode.
+///
+/// Errors are reused as they are, documents are revalidated with their `ETag`
+/// and `Last-Modified` validators.
+type DocumentCache = Mutex<HashMap<Link, Result<Document<'static>, Arc<Error>>>>;
+
+/// Checks the links of the files, or of the link records on stdin if there
+/// are no files, and prints the results.
+///
+/// Returns false if the exit status should be non-zero.
+fn run(opt: &Opt, files: &[String], cache: Option<&DocumentCache>) -> bool {
+    let run_started = Instant::now();
+    let printer = Printer {
+        silence: opt.mute.iter().collect(),
+        silence_paths: &opt.mute_path,
+        only: opt.only.iter().collect(),
+        format: opt.format,
+        template: if opt.link_only {
+            Template::parse("{link}").ok()
+        } else {
+            opt.output_template.clone()
+        },
+        byte_ranges: opt.byte_ranges,
+        color: match opt.color {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal(),
+        },
+        group_by_file: opt.group_by_file,
+        last_path: Mutex::new(None),
+        sarif_results: Mutex::new(vec![]),
+        json_records: Mutex::new(vec![]),
+        fail_on: opt.fail_on.iter().collect(),
+        fail_on_any: opt.fail_on_any,
+        failed: atomic::AtomicBool::new(false),
+        summary_only: opt.summary_only,
+        broken: atomic::AtomicUsize::new(0),
+        log_format: opt.log_format,
+    };
 
     let prefixes: Vec<_> = opt.prefix.iter().map(AsRef::as_ref).collect();
-    let resolver = FragResolver::from(&prefixes);
-    let make_client = if opt.check {
-        if opt.follow {
-            || Some(Client::new_follow())
+    let resolver = FragResolver::from(&prefixes)
+        .github_compat(opt.github_compat)
+        .fragment_soft_fail(opt.fragment_soft_fail);
+    let accepted_statuses: Vec<_> = opt
+        .accept_status
+        .iter()
+        .map(|&status| StatusCode::from_u16(status).unwrap())
+        .collect();
+    // Markdown files given as arguments are only read once, even if linked
+    let anchor_index = Arc::new(AnchorIndex::new(
+        linky::AnchorStyle::from(opt.anchor_style).id_transform(),
+    ));
+    let manifest = opt.manifest.as_ref().map(|path| {
+        Arc::new(read_manifest(path).unwrap_or_else(|err| {
+            error!("reading manifest {}: {}", path.to_string_lossy(), err);
+            process::exit(2);
+        }))
+    });
+    let timeouts = Timeouts {
+        connect: opt.connect_timeout.map(Duration::from_secs),
+        read: opt.read_timeout.map(Duration::from_secs),
+        deadline: opt.deadline.map(Duration::from_secs),
+    };
+    let watchdog = opt.watchdog.map(Duration::from_secs);
+    // Header values may hold secrets, so they are never included in errors
+    let host_headers: Vec<_> = opt
+        .header_for
+        .chunks(2)
+        .map(|pair| match parse_header(&pair[1]) {
+            Ok((name, value)) => (pair[0].as_str(), name, value),
+            Err(err) => {
+                error!("invalid header for host {}: {}", pair[0], err);
+                process::exit(2);
+            }
+        })
+        .collect();
+    let make_client = |check: bool, text_fragments: bool| {
+        if check && !opt.no_network {
+            let builder = host_headers
+                .iter()
+                .fold(Client::builder(), |builder, (host, name, value)| {
+                    builder.host_header(host, name, value.clone())
+                });
+            Some(
+                builder
+                    .follow_redirects(opt.follow)
+                    .user_agent(&opt.user_agent)
+                    .accept(opt.accept.clone())
+                    .proxy(opt.proxy.clone())
+                    .insecure(opt.insecure)
+                    .ca_cert(opt.ca_cert.clone())
+                    .timeouts(timeouts)
+                    .max_redirects(opt.max_redirects)
+                    .build()
+                    .accept_statuses(accepted_statuses.iter().cloned())
+                    .warn_permanent_redirects(opt.warn_permanent_redirect)
+                    .case_sensitive_paths(opt.case_sensitive_paths)
+                    .max_body_bytes(opt.max_body_bytes)
+                    .warn_duplicate_anchors(opt.warn_duplicate_anchors)
+                    .anchor_style(opt.anchor_style.into())
+                    .dns_only(opt.dns_only)
+                    .check_fragments(!opt.no_fragment_check)
+                    .prefer_https(opt.prefer_https)
+                    .unify_404(opt.unify_404)
+                    .wayback_fallback(
+                        Some(Url::parse(WAYBACK_API).unwrap()).filter(|_| opt.wayback_fallback),
+                    )
+                    .allow_redirect_to_https(opt.allow_redirect_to_https)
+                    .allow_same_host_redirect(opt.allow_same_host_redirect)
+                    .offline(opt.offline)
+                    .warn_example_hosts(opt.warn_example_hosts)
+                    .pins(opt.pin.iter().cloned())
+                    .expect_present(opt.expect_present.iter().cloned())
+                    .expect_absent(opt.expect_absent.iter().cloned())
+                    .text_fragments(text_fragments)
+                    .index_files(opt.index.iter().cloned())
+                    .charset_fallback(opt.charset_fallback)
+                    .allow_schemes(opt.allow_scheme.iter().cloned())
+                    .manifest(manifest.clone())
+                    .anchor_index(Some(Arc::clone(&anchor_index))),
+            )
         } else {
-            || Some(Client::new_no_follow())
+            None
         }
-    } else {
-        || None
     };
 
+    let fixes: Mutex<Fixes> = Mutex::new(HashMap::new());
+    let report = Mutex::new(Report::default());
     let o = Orderer {
         heap: Mutex::new(BinaryHeap::new()),
         current: atomic::AtomicUsize::new(0),
-        f: |(record, res)| {
-            print_result(&record, &res, &silence, opt.link_only);
+        f: |(record, res, final_url, elapsed): Outcome| {
+            printer.print(&record, &res, &final_url, elapsed);
+            if opt.report.is_some() {
+                report.lock().unwrap().record(&res);
+            }
+            if let (true, Some(Err(err)), Some(col)) = (opt.fix, &res, record.doc_col) {
+                if let Some(replacement) = replacement(&record, err) {
+                    fixes
+                        .lock()
+                        .unwrap()
+                        .entry(record.doc_path.clone())
+                        .or_default()
+                        .insert((record.doc_line, col, record.link.clone()), replacement);
+                }
+            }
         },
     };
 
-    let root: Option<PathBuf> = opt
+    let roots: Vec<PathBuf> = opt
         .root
-        .as_ref()
-        .map(|root| fs::canonicalize(root).unwrap());
+        .iter()
+        .map(|root| fs::canonicalize(root).unwrap())
+        .collect();
+    let mut root_marker = opt.root_marker.as_ref().map(RootMarker::new);
 
-    if opt.file.is_empty() {
+    let (groups, invalid) = if opt.files_from.is_none() && files.is_empty() {
         let stdin = io::stdin();
         let links = stdin
             .lock()
@@ -214,62 +1664,264 @@ fn main() {
             .map(Result::unwrap)
             .enumerate()
             .map(|(lineno, line)| {
-                Record::from_str(&line).map_err(|e| format!("line {}: {}", lineno + 1, e))
+                opt.stdin_format
+                    .parse(&line)
+                    .map_err(|e| format!("line {}: {}", lineno + 1, e))
             })
-            .map(Result::unwrap);
+            .map(Result::unwrap)
+            .map(|record| (record, false));
         Box::new(Vec::from_iter(links).into_iter()) as Box<dyn Iterator<Item = _>>
     } else {
-        Box::new(opt.file.iter().flat_map(|path| {
-            read_md(path)
+        Box::new(files.iter().flat_map(|path| {
+            let records = read_records(opt, Some(&anchor_index), path).and_then(|records| {
+                let unused = match input_format(opt.input_format, path) {
+                    InputFormat::Markdown if opt.check_unused_defs => read_unused_defs(path)?,
+                    _ => Box::new(iter::empty()),
+                };
+                // With --check, unused definitions are checked like links as well
+                let unused = unused.flat_map(|record| {
+                    let checked = Some((record.clone(), false)).filter(|_| opt.check);
+                    iter::once((record, true)).chain(checked)
+                });
+                Ok(
+                    Box::new(records.map(|record| (record, false)).chain(unused))
+                        as Box<dyn Iterator<Item = _>>,
+                )
+            });
+            records
                 .map_err(|err| error!("reading file {}: {}", escape(Cow::Borrowed(path)), err))
                 .unwrap_or_else(|_| Box::new(iter::empty()))
         })) as Box<dyn Iterator<Item = _>>
     }
-    .filter_map(|record: Record| {
-        record
-            .to_link(&root)
-            .map_err(|err| {
+    .filter_map(|(record, is_unused_def): (Record, bool)| {
+        if is_unused_def {
+            let err = Tag::UnusedDefinition
+                .as_error()
+                .context("label", record.text.clone().unwrap_or_default());
+            return Some((record, Err(err)));
+        }
+        let marker_root = root_marker
+            .as_mut()
+            .and_then(|marker| marker.find_for_doc(&record.doc_path));
+        let link = match marker_root {
+            Some(root) => record.to_link(&Some(root), opt.base_url.as_ref()),
+            None => record.to_link_with_roots(&roots, opt.base_url.as_ref()),
+        };
+        let link = if opt.normalize_urls {
+            link.map(|(link, fragment)| (link.without_trailing_slash(), fragment))
+        } else {
+            link
+        };
+        let link = if opt.strip_query.is_empty() {
+            link
+        } else {
+            link.map(|(link, fragment)| (link.without_query_params(&opt.strip_query), fragment))
+        };
+        match link {
+            Err(err) if !opt.check => {
                 error!(
                     "{}:{}: {}: {}",
                     record.doc_path.to_string_lossy(),
                     record.doc_line,
                     err,
                     record.link
-                )
-            })
-            .map(|(base, fragment)| Some((record, base, fragment)))
-            .unwrap_or(None)
+                );
+                None
+            }
+            res => Some((record, res)),
+        }
     })
     .enumerate()
-    .fold(HashMap::new(), group_fragments)
-    .into_par_iter()
-    .flat_map(|(base, fragments)| {
-        let document = make_client()
-            .as_ref()
-            .map(|client| client.fetch_link(opt.urldecode, &base));
-
-        // Log all found anchors at the debug level
-        if log_enabled!(log::Level::Debug) {
-            debug!("In document: {}", &base);
-            if let Some(Ok(document)) = &document {
-                let mut ids: Vec<_> = document.ids.iter().collect();
-                ids.sort_unstable();
-                for fragment in ids {
-                    debug!("  found anchor: {}", fragment);
+    .fold((HashMap::new(), vec![]), group_fragments);
+
+    let progress = if opt.progress {
+        Some(Progress::new(
+            invalid.len() + groups.values().map(Vec::len).sum::<usize>(),
+        ))
+    } else {
+        None
+    };
+
+    // Groups that haven't started when the limit is reached are never fetched,
+    // while groups already being checked run to completion
+    let errors = atomic::AtomicU64::new(0);
+    let is_aborted = || {
+        opt.max_errors
+            .is_some_and(|max| errors.load(atomic::Ordering::SeqCst) >= max)
+    };
+
+    invalid
+        .into_par_iter()
+        .chain(groups.into_par_iter().flat_map(|(base, fragments)| {
+            if is_aborted() {
+                return vec![];
+            }
+            // Same-document links are checked even without --check
+            let is_same_doc = |record: &Record| record.is_same_doc(&base);
+            // The text content of documents is only kept if it's checked
+            let text_fragments = fragments
+                .iter()
+                .any(|(_, fragment, _)| fragment.as_ref().is_some_and(|f| f.contains(":~:")));
+            let client = make_client(
+                opt.check || fragments.iter().any(|(_, _, record)| is_same_doc(record)),
+                text_fragments,
+            )
+            .map(Arc::new);
+            let started = Instant::now();
+            // Abandoned fetches keep the Orderer going, see fetch_watched
+            let cached = match (cache, &base) {
+                (Some(cache), Link::Url(_)) => cache.lock().unwrap().get(&base).cloned(),
+                _ => None,
+            };
+            let document = client.as_ref().map(|client| match cached {
+                Some(Ok(document)) if text_fragments && document.lowercase_text.is_none() => {
+                    fetch_watched(client, opt.urldecode, &base, watchdog)
+                }
+                Some(Ok(document))
+                    if document.etag.is_none() && document.last_modified.is_none() =>
+                {
+                    Ok(document)
                 }
+                Some(Ok(document)) => client.revalidate(opt.urldecode, &base, &document),
+                Some(Err(err)) => Err(err),
+                None => fetch_watched(client, opt.urldecode, &base, watchdog),
+            });
+            if let (Some(cache), Link::Url(_), Some(document)) = (cache, &base, &document) {
+                cache.lock().unwrap().insert(base.clone(), document.clone());
             }
-        }
+            if let (Some(_), Some(document)) = (&opt.report, &document) {
+                report
+                    .lock()
+                    .unwrap()
+                    .record_document(&base, document, started.elapsed());
+            }
+            let elapsed = Some(started.elapsed()).filter(|_| opt.timings && document.is_some());
 
-        fragments
-            .into_iter()
-            .map(|(index, fragment, record)| {
-                let value = resolver.link(&document, &base, &fragment);
-                Item {
-                    index,
-                    value: (record, value),
+            // Log all found anchors at the debug level
+            if log_enabled!(log::Level::Debug) {
+                debug!("In document: {}", &base);
+                if let Some(Ok(document)) = &document {
+                    let mut ids: Vec<_> = document.ids.iter().collect();
+                    ids.sort_unstable();
+                    for fragment in ids {
+                        debug!("  found anchor: {}", fragment);
+                    }
                 }
-            })
-            .collect::<Vec<_>>()
-    })
-    .for_each(|item| o.push(item));
+            }
+
+            let final_url = match &document {
+                Some(Ok(document)) if opt.show_final_url => document.final_url.clone(),
+                _ => None,
+            };
+
+            fragments
+                .into_iter()
+                .map(|(index, fragment, record)| {
+                    let value = match base {
+                        Link::Url(_) if opt.no_network => {
+                            Some(Err(Arc::new(Tag::Skipped.as_error())))
+                        }
+                        _ => client
+                            .as_ref()
+                            .filter(|_| opt.check || is_same_doc(&record))
+                            .map(|client| {
+                                check_link(
+                                    client,
+                                    &resolver,
+                                    &base,
+                                    &fragment,
+                                    opt.urldecode,
+                                    document.as_ref(),
+                                )
+                            }),
+                    };
+                    Item {
+                        index,
+                        value: (record, value, final_url.clone(), elapsed),
+                    }
+                })
+                .collect::<Vec<_>>()
+        }))
+        .for_each(|item| {
+            if let Some(ref progress) = progress {
+                progress.record(&item.value.1);
+            }
+            if let (Some(_), Some(Err(err))) = (opt.max_errors, &item.value.1) {
+                if !err.tag.is_warning() && !printer.is_muted(&item.value.0, &err.tag) {
+                    errors.fetch_add(1, atomic::Ordering::SeqCst);
+                }
+            }
+            o.push(item)
+        });
+    let aborted = is_aborted();
+    if aborted {
+        o.flush();
+    }
+
+    // Only files given as arguments are rewritten, never records from stdin
+    for (path, replacements) in fixes.into_inner().unwrap() {
+        let is_markdown = files.iter().any(|file| {
+            Path::new(file) == path
+                && matches!(input_format(opt.input_format, file), InputFormat::Markdown)
+        });
+        if is_markdown {
+            if let Err(err) = fix_file(&path, &replacements, opt.bare_urls) {
+                error!("fixing file {}: {}", path.to_string_lossy(), err);
+            }
+        }
+    }
+
+    let ok = printer.finish();
+    if let Some(ref path) = opt.report {
+        let report = report.into_inner().unwrap().to_json(run_started.elapsed());
+        if let Err(err) = fs::write(path, format!("{report:#}\n")) {
+            error!("writing report {}: {}", path.to_string_lossy(), err);
+            process::exit(2);
+        }
+    }
+    if opt.no_network {
+        eprintln!("NOTE: no network requests were issued (--no-network)");
+    }
+    if aborted {
+        eprintln!(
+            "NOTE: stopped checking links after {} failures (--max-errors)",
+            errors.load(atomic::Ordering::SeqCst)
+        );
+    }
+    ok && !aborted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog() {
+        let link = Link::Url(Url::parse("http://example.com/").unwrap());
+        let watchdog = Duration::from_millis(50);
+        assert_eq!(watched(watchdog, &link, || Ok(1)).ok(), Some(1));
+
+        let err = watched(watchdog, &link, || {
+            thread::sleep(Duration::from_secs(1));
+            Ok(())
+        })
+        .err()
+        .unwrap();
+        assert_eq!(err.tag, Tag::Timeout);
+        assert!(err
+            .contexts()
+            .any(|context| context == ("phase", "watchdog")));
+
+        let err = watched(
+            Duration::from_secs(10),
+            &link,
+            || -> Result<(), Arc<Error>> { panic!("fetch panicked") },
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.tag, Tag::IoError);
+        assert!(err
+            .contexts()
+            .any(|context| context == ("link", "http://example.com/")));
+    }
 }
@@ -0,0 +1,384 @@
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn summary_only_shows_links_failing_the_run() {
+    let path = std::env::temp_dir().join("linky-summary-only-test.md");
+    fs::write(&path, "# Heading\n\n[x](#HEADING)\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_linky"))
+        .args(["--check", "--summary-only", "--fail-on-any"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains("CASE_FRAG #HEADING"));
+    assert!(stderr.contains("1 broken link"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_linky"))
+        .args(["--check", "--summary-only"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("broken link"));
+}
+
+/// Serves the responses chosen by `respond` for each request until the
+/// process exits, and returns the base URL.
+fn serve(respond: fn(&str) -> &'static str) -> String {
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut request = [0; 4096];
+            let n = stream.read(&mut request).unwrap_or(0);
+            let response = respond(&String::from_utf8_lossy(&request[..n]));
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    base
+}
+
+/// Serves a permanent redirect from /old to /new.
+fn serve_redirect() -> String {
+    serve(|request| {
+        if request.contains(" /old ") {
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: /new\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        } else {
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 6\r\nConnection: close\r\n\r\n<html>"
+        }
+    })
+}
+
+#[test]
+fn fix_keeps_file_encoding() {
+    let base = serve_redirect();
+    let bom = std::env::temp_dir().join("linky-fix-bom-test.md");
+    let utf16 = std::env::temp_dir().join("linky-fix-utf16-test.md");
+    fs::write(&bom, format!("\u{feff}[x]({base}/old)\n")).unwrap();
+    let text: Vec<u8> = format!("[x]({base}/old)\n")
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    fs::write(&utf16, [&[0xFF, 0xFE][..], &text].concat()).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_linky"))
+        .args(["--check", "--follow", "--warn-permanent-redirect", "--fix"])
+        .arg(&bom)
+        .arg(&utf16)
+        .output()
+        .unwrap();
+    let fixed = fs::read(&bom).unwrap();
+    let untouched = fs::read(&utf16).unwrap();
+    for path in [&bom, &utf16] {
+        fs::remove_file(path).unwrap();
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".bak");
+        let _ = fs::remove_file(backup);
+    }
+    assert_eq!(fixed, format!("\u{feff}[x]({base}/new)\n").into_bytes());
+    assert_eq!(untouched, [&[0xFF, 0xFE][..], &text].concat());
+}
+
+#[test]
+fn accepted_statuses_pass() {
+    let base = serve(|request| {
+        if request.contains(" /forbidden ") {
+            "HTTP/1.1 403 Forbidden\r\nContent-Type: text/html\r\nContent-Length: 6\r\nConnection: close\r\n\r\n<html>"
+        } else {
+            "HTTP/1.1 999 Request Denied\r\nContent-Type: text/html\r\nContent-Length: 6\r\nConnection: close\r\n\r\n<html>"
+        }
+    });
+    let path = std::env::temp_dir().join("linky-accept-status-test.md");
+    fs::write(
+        &path,
+        format!("[a]({base}/forbidden)\n[b]({base}/denied)\n"),
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_linky"))
+            .args(["--check", "--fail-on-any"])
+            .args(args)
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+    let rejected = run(&[]);
+    let accepted = run(&["--accept-status", "403", "--accept-status", "999"]);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(rejected.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&rejected.stdout);
+    assert!(stdout.contains(&format!("HTTP_403 {base}/forbidden")));
+    assert!(stdout.contains(&format!("HTTP_999 {base}/denied")));
+    assert_eq!(accepted.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&accepted.stdout);
+    assert!(stdout.contains(&format!("OK {base}/forbidden")));
+    assert!(stdout.contains(&format!("OK {base}/denied")));
+}
+
+/// Runs linky with the arguments on a temporary Markdown file with the
+/// content, and returns the output along with the path of the file.
+fn run_on(name: &str, content: &str, args: &[&str]) -> (std::process::Output, String) {
+    let path = std::env::temp_dir().join(name);
+    fs::write(&path, content).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_linky"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    (output, path.to_string_lossy().into_owned())
+}
+
+#[test]
+fn github_format() {
+    let (output, path) = run_on(
+        "linky-github-format-test.md",
+        "# Heading\n\n[a](missing.md)\n[b](#HEADING)\n[c](#heading)\n",
+        &["--check", "--format", "github"],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            format!("::error file={path},line=3,col=1::Document not found: missing.md"),
+            format!(
+                "::warning file={path},line=4,col=1::Fragment not found case-sensitively: #HEADING"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn sarif_format() {
+    let (output, path) = run_on(
+        "linky-sarif-format-test.md",
+        "# Heading\n\n[a](missing.md)\n[b](#HEADING)\n[c](#heading)\n",
+        &["--check", "--format", "sarif"],
+    );
+    let log: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(log["version"], "2.1.0");
+    let run = &log["runs"][0];
+    assert_eq!(run["tool"]["driver"]["name"], "linky");
+    let rules: Vec<_> = run["tool"]["driver"]["rules"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|rule| rule["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(rules, vec!["NO_DOC", "CASE_FRAG"]);
+    let results = run["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    let (error, warning) = (&results[0], &results[1]);
+    assert_eq!(error["ruleId"], "NO_DOC");
+    assert_eq!(error["level"], "error");
+    assert_eq!(error["message"]["text"], "Document not found: missing.md");
+    let location = &error["locations"][0]["physicalLocation"];
+    assert_eq!(location["artifactLocation"]["uri"], path.as_str());
+    assert_eq!(location["region"]["startLine"], 3);
+    assert_eq!(location["region"]["startColumn"], 1);
+    assert_eq!(warning["ruleId"], "CASE_FRAG");
+    assert_eq!(warning["level"], "warning");
+    let location = &warning["locations"][0]["physicalLocation"];
+    assert_eq!(location["region"]["startLine"], 4);
+}
+
+#[test]
+fn fail_on_tags() {
+    let content = "# Heading\n\n[a](missing.md)\n[b](#HEADING)\n";
+    let exit_code = |args: &[&str]| {
+        let mut args = args.to_vec();
+        args.insert(0, "--check");
+        run_on("linky-fail-on-test.md", content, &args)
+            .0
+            .status
+            .code()
+    };
+    assert_eq!(exit_code(&[]), Some(0));
+    assert_eq!(exit_code(&["--fail-on", "NO_FRAG"]), Some(0));
+    assert_eq!(exit_code(&["--fail-on", "CASE_FRAG"]), Some(1));
+    assert_eq!(
+        exit_code(&["--fail-on", "NO_FRAG", "--fail-on", "NO_DOC"]),
+        Some(1)
+    );
+    assert_eq!(
+        exit_code(&["--fail-on", "NO_DOC", "--mute", "NO_DOC"]),
+        Some(1)
+    );
+}
+
+#[test]
+fn output_template() {
+    let base = serve_redirect();
+    let template =
+        "{{{path}:{line}:{col}}} {tag} {link} [{text}] ({title}) <{final_url}> {ms} {start}-{end}";
+    let (output, path) = run_on(
+        "linky-output-template-test.md",
+        &format!("# Heading\n\n[a](missing.md \"Title\")\n[b]({base}/old)\n"),
+        &[
+            "--check",
+            "--follow",
+            "--show-final-url",
+            "--timings",
+            "--byte-ranges",
+            "--output-template",
+            template,
+        ],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    // Elapsed times vary, so they are only checked to be numbers
+    let (prefix, rest) = lines[0].split_at(lines[0].find("<> ").unwrap() + 3);
+    assert_eq!(
+        prefix,
+        format!("{{{path}:3:1}} NO_DOC missing.md [a] (Title) <> ")
+    );
+    let (ms, span) = rest.split_once(' ').unwrap();
+    assert!(ms.parse::<u64>().is_ok());
+    assert_eq!(span, "11-34");
+    assert!(lines[1].starts_with(&format!(
+        "{{{path}:4:1}} OK {base}/old [b] () <{base}/new> "
+    )));
+
+    for (template, message) in [
+        ("{nope}", "unknown placeholder {nope}"),
+        ("a}b", "unmatched '}'; write '}}' for a literal brace"),
+        ("{path", "unterminated placeholder"),
+    ] {
+        let (output, _) = run_on(
+            "linky-output-template-error-test.md",
+            "",
+            &["--output-template", template],
+        );
+        assert_eq!(output.status.code(), Some(2));
+        assert!(String::from_utf8_lossy(&output.stderr).contains(message));
+    }
+}
+
+#[test]
+fn jsonl_matches_json() {
+    let content = "# Heading\n\n[a](missing.md \"Title\")\n[b](#HEADING)\n[c](#heading)\n";
+    let (jsonl, _) = run_on(
+        "linky-jsonl-test.md",
+        content,
+        &["--check", "--byte-ranges", "--format", "jsonl"],
+    );
+    let (json, _) = run_on(
+        "linky-jsonl-test.md",
+        content,
+        &["--check", "--byte-ranges", "--format", "json"],
+    );
+    let lines: Vec<serde_json::Value> = String::from_utf8_lossy(&jsonl.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let array: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(array, serde_json::Value::from(lines.clone()));
+    let keys: Vec<_> = lines[0].as_object().unwrap().keys().collect();
+    assert_eq!(
+        keys,
+        vec![
+            "col",
+            "context",
+            "end",
+            "final_url",
+            "line",
+            "link",
+            "ms",
+            "path",
+            "start",
+            "tag",
+            "text",
+            "title"
+        ]
+    );
+    assert_eq!(lines[0]["tag"], "NO_DOC");
+    assert_eq!(lines[0]["title"], "Title");
+    assert_eq!(lines[1]["context"]["anchor"], "#heading");
+}
+
+#[test]
+fn max_errors() {
+    let content: String = (1..=1000)
+        .map(|i| format!("[l{i}](missing{i}.md)\n"))
+        .collect();
+    let (output, path) = run_on(
+        "linky-max-errors-test.md",
+        &content,
+        &["--check", "--max-errors", "1"],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<usize> = stdout
+        .lines()
+        .map(|line| {
+            let rest = line.strip_prefix(&format!("{path}:")).unwrap();
+            let (line, rest) = rest.split_once(':').unwrap();
+            assert!(rest.contains(" NO_DOC missing"));
+            line.parse().unwrap()
+        })
+        .collect();
+    assert!(!lines.is_empty() && lines.len() < 1000);
+    assert!(lines.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("(--max-errors)"));
+}
+
+#[test]
+fn summary_only() {
+    let (output, path) = run_on(
+        "linky-summary-only-broken-test.md",
+        "# Heading\n\n[a](#heading)\n[b](missing.md)\n[c](#HEADING)\n[d](other.md)\n",
+        &["--check", "--summary-only"],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            format!("{path}:4:1: NO_DOC missing.md"),
+            format!("{path}:6:1: NO_DOC other.md"),
+        ]
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.lines().last(), Some("2 broken links"));
+}
+
+#[test]
+fn watchdog_abandons_stalled_fetches() {
+    use std::io::Read;
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    // Reads requests but never responds, keeping the connections open
+    std::thread::spawn(move || {
+        let mut streams = vec![];
+        for mut stream in listener.incoming().flatten() {
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request);
+            streams.push(stream);
+        }
+    });
+    let started = std::time::Instant::now();
+    let (output, path) = run_on(
+        "linky-watchdog-test.md",
+        &format!("[a]({base}/stall)\n[b](missing.md)\n[c]({base}/stall#frag)\n"),
+        &["--check", "--watchdog", "1"],
+    );
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            format!("{path}:1:1: TIMEOUT {base}/stall"),
+            format!("{path}:2:1: NO_DOC missing.md"),
+            format!("{path}:3:1: TIMEOUT {base}/stall#frag"),
+        ]
+    );
+}